@@ -0,0 +1,26 @@
+use std::error::Error as StdError;
+use tokio_util::sync::CancellationToken;
+use crate::config::RestartPolicy;
+use crate::telemetry::{Diagnose, TelemetrySender};
+use crate::queue::{CommandQueue, QueuePolicy};
+
+/// Something that moves, e.g. a Marlin printer over serial.
+pub trait MotionBackend: Send + 'static {
+    type Error: StdError + Diagnose + Send + Sync + 'static;
+
+    /// for logs, e.g. "USB"
+    fn name(&self) -> &'static str;
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::OnFailure
+    }
+
+    fn queue_policy(&self) -> QueuePolicy {
+        QueuePolicy::LatestWins
+    }
+
+    /// Carry out commands from `queue` until the token is cancelled. `queue` is borrowed so it
+    /// survives a restart, along with anything still in it.
+    fn run(&mut self, queue: &mut CommandQueue, telemetry: TelemetrySender, token: CancellationToken)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
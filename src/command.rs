@@ -0,0 +1,95 @@
+//! What gets sent to the printer, the same whichever input it came from and whichever
+//! [`crate::backend::MotionBackend`] carries it out.
+
+use std::fmt::Display;
+use std::time::Instant;
+use serde::Serialize;
+use crate::metrics::METRICS;
+
+#[derive(Debug, Clone)]
+pub enum Command {
+    Movement(LinearAction),
+    Home,
+    /// stop where we are and ignore movement until [`Command::Resume`]. (M410)
+    Pause,
+    /// like pause, then slowly move to the bottom of the stroke.
+    Park,
+    /// carry on streaming after a pause or park
+    Resume,
+    /// emergency stop, the printer has to be reset after this. (M112)
+    Halt,
+    /// a line of G-code typed by hand, sent as is (even while paused)
+    Raw(String),
+}
+
+/// Where a command came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum Source {
+    Intiface,
+    XToys,
+    Keyboard,
+    /// typed into the console
+    Console,
+    /// over the HTTP API or the control socket
+    Remote,
+    /// sent by us, e.g. homing on connect or position polls
+    Internal,
+}
+
+impl Source {
+    pub const ALL: [Source; 6] = [Source::Intiface, Source::XToys, Source::Keyboard, Source::Console, Source::Remote, Source::Internal];
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+/// A command on its way to the printer.
+#[derive(Debug, Clone)]
+pub struct QueuedCommand {
+    pub source: Source,
+    pub command: Command,
+    /// when it came in, for measuring latency
+    pub received: Instant,
+    /// lasts until the printer acknowledges the command
+    #[cfg(feature = "tracing")]
+    pub span: tracing::Span,
+}
+
+impl QueuedCommand {
+    /// Stamped with the time it came in.
+    pub fn new(source: Source, command: Command) -> Self {
+        METRICS.command_received(source);
+        QueuedCommand {
+            source,
+            command,
+            received: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!("command", %source),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LinearAction {
+    pub action: Action,
+    pub id: u32,
+    /// 0 to 1, how far along the stroke
+    pub position: f32,
+    pub modifier: Option<LinearModifier>
+}
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub enum Action {
+    MOVE,
+    ROTATE,
+    VIBRATE,
+    AUXILLARY
+}
+
+#[derive(Debug, Clone)]
+pub enum LinearModifier {
+    TIME(u32),
+    SPEED(u32)
+}
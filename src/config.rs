@@ -1,26 +1,308 @@
 use std::fmt::Display;
+use std::path::PathBuf;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::pipeline::Stage;
+use crate::queue::QueuePolicy;
+use crate::recorder::RecordPoint;
 
-#[derive(Debug)]
-#[derive(Default, Clone)]
+/// A setting picked from a fixed list, shown and parsed by its variant name.
+pub trait Choice: Copy + Display + FromStr + 'static {
+    const ALL: &'static [Self];
+}
+
+/// [`Choice`], `FromStr` and `Display` for a fieldless enum, e.g.
+/// `choice!(FlowControl, "flow control", [None, Software, Hardware]);`
+macro_rules! choice {
+    ($name:ident, $what:literal, [$($variant:ident),+ $(,)?]) => {
+        impl $crate::config::Choice for $name {
+            const ALL: &'static [$name] = &[$($name::$variant),+];
+        }
+
+        impl std::str::FromStr for $name {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                <$name as $crate::config::Choice>::ALL.iter().copied()
+                    .find(|p| p.to_string() == s)
+                    .ok_or_else(|| format!(concat!("Unknown ", $what, " {}"), s))
+            }
+        }
+
+        impl std::fmt::Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "{:?}", self)
+            }
+        }
+    };
+}
+pub(crate) use choice;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error("no config directory could be found for this user")]
+    NoConfigDir,
+    #[error("profile \"{0}\": {1}")]
+    Invalid(String, String),
+}
+
+/// Every saved machine/service profile, and which one is in use.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    pub profiles: Vec<Profile>,
+    /// index into `profiles`, remembered between runs.
+    pub active_profile: usize,
+    pub http: HttpConfig,
+    /// JSON-RPC for local scripts, see [`crate::control`]
+    pub control_socket: bool,
+    pub metrics: MetricsConfig,
+    pub recording: RecordingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            profiles: vec![Profile::default()],
+            active_profile: 0,
+            http: HttpConfig::default(),
+            control_socket: true,
+            metrics: MetricsConfig::default(),
+            recording: RecordingConfig::default(),
+        }
+    }
+}
+
+/// The local control API, shared by every profile.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    /// `127.0.0.1:port` for this machine only, `0.0.0.0:port` for the whole LAN
+    pub bind: String,
+    /// where the web UI's live updates are served, the page itself is on `bind`
+    pub websocket_bind: String,
+}
+
+/// Prometheus `/metrics`, see [`crate::metrics`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetricsConfig {
+    pub enabled: bool,
+    pub bind: String,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            enabled: false,
+            bind: "127.0.0.1:9464".to_string(),
+        }
+    }
+}
+
+/// Saving sessions as funscripts, see [`crate::recorder`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecordingConfig {
+    pub enabled: bool,
+    pub point: RecordPoint,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: false,
+            bind: "127.0.0.1:8080".to_string(),
+            websocket_bind: "127.0.0.1:8081".to_string(),
+        }
+    }
+}
+
+/// A named set of settings for one machine (e.g. "Ender 3", "Ender 5").
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    pub name: String,
     pub machine_config: MachineConfig,
     pub websocket_config: WebsocketConfig,
+    /// filters movement goes through on its way to the printer, in order
+    pub pipeline: Vec<Stage>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        let websocket_config = WebsocketConfig::default();
+        Profile {
+            name: "Default".to_string(),
+            machine_config: MachineConfig::default(),
+            pipeline: websocket_config.provider.default_pipeline(),
+            websocket_config,
+        }
+    }
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/inti-e3m/config.json` (or the platform equivalent)
+    pub fn path() -> Result<PathBuf, ConfigError> {
+        let mut path = dirs::config_dir().ok_or(ConfigError::NoConfigDir)?;
+        path.push("inti-e3m");
+        path.push("config.json");
+        Ok(path)
+    }
+
+    /// Load the saved config, falling back to defaults if there isn't one yet.
+    pub fn load() -> Result<Config, ConfigError> {
+        let path = Self::path()?;
+        if !path.exists() { return Ok(Config::default()); }
+        let mut json: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        // profiles saved before the pipeline existed get whatever their provider used to do
+        for profile in json.get_mut("profiles").and_then(serde_json::Value::as_array_mut).into_iter().flatten() {
+            if profile.get("pipeline").is_none() {
+                let provider = serde_json::from_value(profile["websocket_config"]["provider"].clone()).unwrap_or(ServiceProvider::INTI);
+                profile["pipeline"] = serde_json::to_value(provider.default_pipeline())?;
+            }
+        }
+        let mut config: Config = serde_json::from_value(json)?;
+        config.sanitize();
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Move an unreadable config out of the way, so saving the defaults we fell back to doesn't
+    /// overwrite it. Returns where it went.
+    pub fn back_up() -> Result<PathBuf, ConfigError> {
+        let path = Self::path()?;
+        let backup = path.with_file_name(chrono::Local::now().format("config.%Y-%m-%d_%H-%M-%S.json.bak").to_string());
+        std::fs::rename(&path, &backup)?;
+        Ok(backup)
+    }
+
+    /// Make sure there's a profile and `active_profile` points at one, and that profile names are
+    /// unique so each can be selected, e.g. after a hand edit.
+    pub fn sanitize(&mut self) {
+        if self.profiles.is_empty() { self.profiles.push(Profile::default()); }
+        self.active_profile = self.active_profile.min(self.profiles.len() - 1);
+        for i in 1..self.profiles.len() {
+            if self.profiles[..i].iter().any(|p| p.name == self.profiles[i].name) {
+                self.profiles[i].name = self.unique_name(&self.profiles[i].name);
+            }
+        }
+    }
+
+    /// Catch settings that are fine on their own but not together, e.g. from a hand edit.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for profile in &self.profiles {
+            profile.machine_config.validate().map_err(|e| ConfigError::Invalid(profile.name.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), ConfigError> {
+        let path = Self::path()?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profiles[self.active_profile]
+    }
+
+    pub fn profile_mut(&mut self) -> &mut Profile {
+        &mut self.profiles[self.active_profile]
+    }
+
+    /// Make the profile called `name` active.
+    pub fn select_profile(&mut self, name: &str) -> Result<(), String> {
+        self.active_profile = self.profiles.iter()
+            .position(|p| p.name == name)
+            .ok_or_else(|| format!("No profile called {name}"))?;
+        Ok(())
+    }
+
+    /// Rename the active profile, names have to be unique.
+    pub fn rename_profile(&mut self, name: &str) -> Result<(), String> {
+        if self.profiles.iter().enumerate().any(|(i, p)| i != self.active_profile && p.name == name) {
+            return Err(format!("There's already a profile called {name}"));
+        }
+        self.profile_mut().name = name.to_string();
+        Ok(())
+    }
+
+    /// Add a fresh profile with default settings and make it active.
+    pub fn create_profile(&mut self) {
+        let name = self.unique_name("Profile");
+        self.profiles.push(Profile { name, ..Profile::default() });
+        self.active_profile = self.profiles.len() - 1;
+    }
+
+    /// Copy the active profile and make the copy active.
+    pub fn duplicate_profile(&mut self) {
+        let mut profile = self.profile().clone();
+        profile.name = self.unique_name(&format!("{} copy", profile.name));
+        self.profiles.insert(self.active_profile + 1, profile);
+        self.active_profile += 1;
+    }
+
+    /// Remove the active profile. The last remaining profile can't be deleted.
+    pub fn delete_profile(&mut self) -> bool {
+        if self.profiles.len() <= 1 { return false; }
+        self.profiles.remove(self.active_profile);
+        self.active_profile = self.active_profile.min(self.profiles.len() - 1);
+        true
+    }
+
+    fn unique_name(&self, base: &str) -> String {
+        let taken = |name: &str| self.profiles.iter().any(|p| p.name == name);
+        if !taken(base) { return base.to_string(); }
+        (2..).map(|n| format!("{base} {n}")).find(|name| !taken(name)).unwrap()
+    }
 }
-#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone)]
+
+#[derive(Debug, Ord, PartialOrd, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
 pub enum ServiceProvider {
     EXTOY,
     INTI,
 }
 
-impl Display for ServiceProvider {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{:?}", self)
+choice!(ServiceProvider, "service provider", [EXTOY, INTI]);
+
+impl ServiceProvider {
+    /// XToys' durations come out about 5x too long for a printer.
+    pub fn default_pipeline(&self) -> Vec<Stage> {
+        match self {
+            ServiceProvider::EXTOY => vec![Stage::TimeScale(0.2)],
+            ServiceProvider::INTI => vec![],
+        }
     }
 }
-#[derive(Debug, Clone)]
+
+/// When the supervisor should bring a side of the server back up after it stops.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+pub enum RestartPolicy {
+    Never,
+    /// only if it stopped with an error
+    OnFailure,
+    /// also after a clean finish, e.g. an XToys client disconnecting
+    Always,
+}
+
+choice!(RestartPolicy, "restart policy", [Never, OnFailure, Always]);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct WebsocketConfig {
     pub provider: ServiceProvider,
     pub ws: String,
+    pub restart: RestartPolicy,
 }
 
 impl Default for WebsocketConfig {
@@ -28,24 +310,162 @@ impl Default for WebsocketConfig {
         WebsocketConfig {
             provider: ServiceProvider::INTI,
             ws: "ws://localhost:54817".to_string(),
+            restart: RestartPolicy::Always,
         }
     }
 }
-#[derive(Debug, Clone)]
+/// Identifies a printer by its USB descriptor instead of its (plug order dependant) device path.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UsbMatch {
+    pub vid: u16,
+    pub pid: u16,
+    /// needed to tell two identical boards apart, a lot of cheap ch340 boards don't have one though
+    pub serial_number: Option<String>,
+}
+
+/// `vid:pid` or `vid:pid:serial`, with the ids in hex like lsusb prints them.
+impl Display for UsbMatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vid, self.pid)?;
+        if let Some(serial) = &self.serial_number {
+            write!(f, ":{}", serial)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for UsbMatch {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        let mut hex = |name: &str| {
+            let part = parts.next().ok_or_else(|| format!("Missing {name}, expected vid:pid[:serial]"))?;
+            u16::from_str_radix(part, 16).map_err(|_| format!("Invalid {name} `{part}`"))
+        };
+        let vid = hex("vendor id")?;
+        let pid = hex("product id")?;
+        let serial_number = parts.next().filter(|s| !s.is_empty()).map(str::to_string);
+        Ok(UsbMatch { vid, pid, serial_number })
+    }
+}
+
+/// What happens to motion that arrives while the printer is unplugged.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub enum DisconnectPolicy {
+    /// throw it away
+    #[default]
+    Drop,
+    /// keep the newest movement and go there once reconnected and homed
+    HoldLatest,
+}
+
+choice!(DisconnectPolicy, "disconnect policy", [Drop, HoldLatest]);
+
+/// Serial flow control, mirrors `tokio_serial::FlowControl` so it can be saved.
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub enum FlowControl {
+    #[default]
+    None,
+    Software,
+    Hardware,
+}
+
+choice!(FlowControl, "flow control", [None, Software, Hardware]);
+
+impl From<FlowControl> for tokio_serial::FlowControl {
+    fn from(value: FlowControl) -> Self {
+        match value {
+            FlowControl::None => tokio_serial::FlowControl::None,
+            FlowControl::Software => tokio_serial::FlowControl::Software,
+            FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct MachineConfig {
     pub file: String,
+    /// if set, `file` is ignored and the port is found by USB id when connecting.
+    pub usb_id: Option<UsbMatch>,
+    /// 250000 for most Marlin builds, 115200 for stock Ender 3 V2 / Creality 4.2.x firmware.
+    pub baud_rate: u32,
+    pub flow_control: FlowControl,
+    /// pulse DTR/RTS when opening, which resets most boards so we get a clean start (and a banner).
+    pub reset_on_open: bool,
     pub throw: u32,
     pub max_movement: u32,
-    pub max_acceleration: u32
+    pub max_acceleration: u32,
+    /// moves asking to go faster than this (mm/min) are slowed down to it
+    pub max_feedrate: u32,
+    pub disconnect_policy: DisconnectPolicy,
+    pub restart: RestartPolicy,
+    pub queue_policy: QueuePolicy,
+}
+
+impl MachineConfig {
+    /// X at the bottom of the stroke, the carriage moves between here and `throw`.
+    pub fn stroke_base(&self) -> f32 {
+        self.throw.saturating_sub(self.max_movement) as f32
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.throw == 0 || self.max_movement == 0 {
+            return Err("movement distance and max throw have to be more than 0 mm".to_string());
+        }
+        if self.max_movement > self.throw {
+            return Err(format!("movement distance ({} mm) is longer than the max throw ({} mm)", self.max_movement, self.throw));
+        }
+        Ok(())
+    }
 }
 
 impl Default for MachineConfig {
     fn default() -> Self {
         MachineConfig {
             file: "/dev/ttyUSB0".to_string(),
+            usb_id: None,
+            baud_rate: 250000,
+            flow_control: FlowControl::None,
+            reset_on_open: false,
             throw: 240,
             max_movement: 100,
-            max_acceleration: 100000
+            max_acceleration: 100000,
+            max_feedrate: 12000,
+            disconnect_policy: DisconnectPolicy::Drop,
+            restart: RestartPolicy::OnFailure,
+            queue_policy: QueuePolicy::LatestWins,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine(throw: u32, max_movement: u32) -> MachineConfig {
+        MachineConfig { throw, max_movement, ..MachineConfig::default() }
+    }
+
+    #[test]
+    fn machine_validate() {
+        assert!(machine(200, 100).validate().is_ok());
+        assert!(machine(200, 200).validate().is_ok());
+        assert!(machine(100, 200).validate().is_err());
+        assert!(machine(200, 0).validate().is_err());
+        assert!(machine(0, 0).validate().is_err());
+    }
+
+    #[test]
+    fn usb_match() {
+        assert_eq!("1a86:7523".parse(), Ok(UsbMatch { vid: 0x1a86, pid: 0x7523, serial_number: None }));
+        assert_eq!("2341:0042:55739323".parse(), Ok(UsbMatch { vid: 0x2341, pid: 0x42, serial_number: Some("55739323".to_string()) }));
+        // an empty serial is the same as none
+        assert_eq!("2341:0042:".parse::<UsbMatch>().unwrap().serial_number, None);
+        assert_eq!("1A86:7523".parse::<UsbMatch>().unwrap().to_string(), "1a86:7523");
+        assert!("1a86".parse::<UsbMatch>().unwrap_err().contains("product id"));
+        assert!("1a86:xyz".parse::<UsbMatch>().unwrap_err().contains("`xyz`"));
+        assert!("12345:7523".parse::<UsbMatch>().is_err());
+    }
+}
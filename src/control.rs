@@ -0,0 +1,187 @@
+//! JSON-RPC 2.0 over a Unix socket, for shell and Python scripts on the same machine.
+//!
+//! One JSON object per line each way. Methods:
+//! - `command` with e.g. `{"command": "home"}` or `{"command": "move", "position": 50, "ms": 300}`
+//! - `config.get`, and `config.set` with a whole config (as returned by `config.get`)
+//! - `telemetry.subscribe`, after which every [`Telemetry`](crate::telemetry::Telemetry) is sent
+//!   as a `telemetry` notification
+//!
+//! Served by the TUI and the headless daemon (see [`ControlSocket`]), `inti-e3m ctl` is the client.
+
+#[cfg(unix)]
+mod socket;
+
+use std::path::PathBuf;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::{mpsc, oneshot};
+use crate::config::Config;
+use crate::command::{Action, Command, LinearAction, LinearModifier};
+
+pub const JSONRPC: &str = "2.0";
+pub const PARSE_ERROR: i64 = -32700;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+#[cfg(unix)]
+pub use socket::ControlSocket;
+
+/// `$XDG_RUNTIME_DIR/inti-e3m.sock`. Without one it's in a directory of our own in the shared temp
+/// dir, `/tmp/inti-e3m-<uid>/inti-e3m.sock`.
+#[cfg(unix)]
+pub fn socket_path() -> PathBuf {
+    let dir = dirs::runtime_dir()
+        .unwrap_or_else(|| std::env::temp_dir().join(format!("inti-e3m-{}", nix::unistd::geteuid())));
+    dir.join("inti-e3m.sock")
+}
+
+/// What a script asked for that the socket can't answer itself.
+#[derive(Debug)]
+pub enum ControlRequest {
+    Command(Command),
+    GetConfig(oneshot::Sender<Config>),
+    /// replace the whole config
+    SetConfig(Box<Config>),
+}
+
+pub type ControlSender = mpsc::UnboundedSender<ControlRequest>;
+
+/// A printer command as scripts write it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum RemoteCommand {
+    Home,
+    Pause,
+    Park,
+    Resume,
+    Halt,
+    /// `position` in percent of the stroke, taking `ms` to get there
+    Move { position: f32, ms: Option<u32> },
+    /// a line of G-code, sent as is
+    Raw { line: String },
+}
+
+impl From<RemoteCommand> for Command {
+    fn from(command: RemoteCommand) -> Self {
+        match command {
+            RemoteCommand::Home => Command::Home,
+            RemoteCommand::Pause => Command::Pause,
+            RemoteCommand::Park => Command::Park,
+            RemoteCommand::Resume => Command::Resume,
+            RemoteCommand::Halt => Command::Halt,
+            RemoteCommand::Move { position, ms } => Command::Movement(LinearAction {
+                action: Action::MOVE,
+                id: 0,
+                position: (position / 100.0).clamp(0.0, 1.0),
+                modifier: ms.map(LinearModifier::TIME),
+            }),
+            RemoteCommand::Raw { line } => Command::Raw(line),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Method {
+    Command(RemoteCommand),
+    GetConfig,
+    SetConfig(Box<Config>),
+    Subscribe,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Request {
+    pub jsonrpc: String,
+    /// missing for notifications, which get no response
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub id: Value,
+    pub method: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub params: Value,
+}
+
+impl Request {
+    pub fn new(id: u64, method: &str, params: impl Serialize) -> Self {
+        Request { jsonrpc: JSONRPC.to_string(), id: id.into(), method: method.to_string(), params: serde_json::to_value(params).unwrap_or_default() }
+    }
+
+    pub fn method(&self) -> Result<Method, RpcError> {
+        Ok(match self.method.as_str() {
+            "command" => Method::Command(self.params()?),
+            "config.get" => Method::GetConfig,
+            "config.set" => Method::SetConfig(self.params()?),
+            "telemetry.subscribe" => Method::Subscribe,
+            method => return Err(RpcError::new(METHOD_NOT_FOUND, format!("Unknown method `{method}`"))),
+        })
+    }
+
+    fn params<T: DeserializeOwned>(&self) -> Result<T, RpcError> {
+        serde_json::from_value(self.params.clone()).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+    }
+}
+
+/// A response, or a notification if it has a `method` instead of an `id`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    pub jsonrpc: String,
+    /// always there on a response, `null` if the request couldn't be read far enough to find it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub method: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<RpcError>,
+}
+
+impl Response {
+    pub fn reply(id: Value, result: Result<Value, RpcError>) -> Self {
+        let (result, error) = match result {
+            Ok(result) => (Some(result), None),
+            Err(error) => (None, Some(error)),
+        };
+        Response { jsonrpc: JSONRPC.to_string(), id: Some(id), method: None, params: None, result, error }
+    }
+
+    pub fn notification(method: &str, params: Value) -> Self {
+        Response { jsonrpc: JSONRPC.to_string(), id: None, method: Some(method.to_string()), params: Some(params), result: None, error: None }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError { code, message: message.into() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use super::*;
+
+    #[test]
+    fn unreadable_requests_get_a_null_id() {
+        let response = Response::reply(Value::Null, Err(RpcError::new(PARSE_ERROR, "expected value")));
+        let response = serde_json::to_value(response).unwrap();
+        assert_eq!(response["id"], Value::Null);
+        assert!(response.as_object().unwrap().contains_key("id"));
+    }
+
+    #[test]
+    fn notifications_have_no_id() {
+        let notification = serde_json::to_value(Response::notification("telemetry", json!({}))).unwrap();
+        assert!(!notification.as_object().unwrap().contains_key("id"));
+        let reply = serde_json::to_value(Response::reply(json!(3), Ok(Value::Bool(true)))).unwrap();
+        assert_eq!(reply, json!({ "jsonrpc": "2.0", "id": 3, "result": true }));
+    }
+}
@@ -0,0 +1,152 @@
+//! Serves [`crate::control`] on a Unix socket. Requests are handed to whoever runs the server as
+//! [`ControlRequest`]s, the TUI and headless each apply them their own way.
+
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt};
+use std::path::Path;
+use nix::unistd::geteuid;
+use serde_json::Value;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::JoinHandle;
+use crate::control::{socket_path, ControlRequest, ControlSender, Method, Request, Response, RpcError, INTERNAL_ERROR, PARSE_ERROR};
+use crate::telemetry::Telemetry;
+
+/// The listening socket, if it's turned on.
+#[derive(Debug, Default)]
+pub struct ControlSocket {
+    running: Option<JoinHandle<()>>,
+}
+
+impl ControlSocket {
+    pub fn update(&mut self, enabled: bool, requests: &ControlSender, telemetry: &broadcast::Sender<Telemetry>) {
+        match (&self.running, enabled) {
+            (None, true) => self.running = Some(tokio::spawn(serve(requests.clone(), telemetry.clone()))),
+            (Some(handle), false) => {
+                handle.abort();
+                self.running = None;
+                let path = socket_path();
+                if our_socket(&path).unwrap_or(false) {
+                    let _ = std::fs::remove_file(path);
+                }
+                log::info!("Control socket closed");
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn serve(requests: ControlSender, telemetry: broadcast::Sender<Telemetry>) {
+    let path = socket_path();
+    if let Err(e) = private_dir(&path).and_then(|_| remove_stale(&path)) {
+        log::error!("Control socket {} not started: {e}", path.display());
+        return;
+    }
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Could not open control socket {}: {e}", path.display());
+            return;
+        }
+    };
+    log::info!("Control socket listening on {}", path.display());
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => { tokio::spawn(client(stream, requests.clone(), telemetry.clone())); }
+            Err(e) => log::warn!("Control socket could not accept a connection: {e}"),
+        }
+    }
+}
+
+/// The socket's directory has to be ours and closed to everyone else, anyone who can reach the
+/// socket can move the printer. Made if it isn't there yet, e.g. our own one in `/tmp`.
+fn private_dir(path: &Path) -> std::io::Result<()> {
+    let Some(dir) = path.parent() else { return Ok(()) };
+    match std::fs::DirBuilder::new().mode(0o700).create(dir) {
+        Err(e) if e.kind() != ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    // not following links, someone else's link to our dir is still someone else's
+    let metadata = std::fs::symlink_metadata(dir)?;
+    if !metadata.is_dir() || metadata.uid() != geteuid().as_raw() || metadata.mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(ErrorKind::PermissionDenied, format!("{} is not a private directory of ours", dir.display())));
+    }
+    Ok(())
+}
+
+/// A socket file left behind by an instance that didn't shut down cleanly is removed, a live one
+/// isn't, and neither is anything that isn't a socket of ours.
+fn remove_stale(path: &Path) -> std::io::Result<()> {
+    match our_socket(path) {
+        Ok(true) => {}
+        Ok(false) => return Err(std::io::Error::new(ErrorKind::AlreadyExists, "something else is in the way, leaving it alone")),
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(std::io::Error::new(ErrorKind::AddrInUse, "another instance is using it"));
+    }
+    std::fs::remove_file(path)
+}
+
+fn our_socket(path: &Path) -> std::io::Result<bool> {
+    let metadata = std::fs::symlink_metadata(path)?;
+    Ok(metadata.file_type().is_socket() && metadata.uid() == geteuid().as_raw())
+}
+
+async fn client(stream: UnixStream, requests: ControlSender, telemetry: broadcast::Sender<Telemetry>) {
+    let (read, mut write) = stream.into_split();
+    let mut lines = BufReader::new(read).lines();
+    let mut subscription: Option<broadcast::Receiver<Telemetry>> = None;
+    loop {
+        let response = tokio::select! {
+            line = lines.next_line() => match line {
+                Ok(Some(line)) if line.trim().is_empty() => continue,
+                Ok(Some(line)) => match serde_json::from_str::<Request>(&line) {
+                    Ok(request) => {
+                        let id = request.id.clone();
+                        let result = handle(request, &requests, &telemetry, &mut subscription).await;
+                        // notifications don't get an answer
+                        if id.is_null() { continue; }
+                        Response::reply(id, result)
+                    }
+                    Err(e) => Response::reply(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string()))),
+                },
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Control socket client failed: {e}");
+                    break;
+                }
+            },
+            Some(telemetry) = async { Some(subscription.as_mut()?.recv().await) } => match telemetry {
+                Ok(telemetry) => Response::notification("telemetry", serde_json::to_value(telemetry).unwrap_or_default()),
+                Err(RecvError::Lagged(missed)) => {
+                    log::debug!("Control socket client missed {missed} telemetry events");
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+        };
+        let mut json = serde_json::to_string(&response).expect("responses always serialize");
+        json.push('\n');
+        if write.write_all(json.as_bytes()).await.is_err() { break; }
+    }
+}
+
+async fn handle(request: Request, requests: &ControlSender, telemetry: &broadcast::Sender<Telemetry>, subscription: &mut Option<broadcast::Receiver<Telemetry>>) -> Result<Value, RpcError> {
+    let send = |request| requests.send(request).map_err(|_| RpcError::new(INTERNAL_ERROR, "Shutting down"));
+    match request.method()? {
+        Method::Command(command) => send(ControlRequest::Command(command.into()))?,
+        Method::GetConfig => {
+            let (reply, rx) = oneshot::channel();
+            send(ControlRequest::GetConfig(reply))?;
+            let config = rx.await.map_err(|_| RpcError::new(INTERNAL_ERROR, "Shutting down"))?;
+            return serde_json::to_value(config).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()));
+        }
+        Method::SetConfig(config) => send(ControlRequest::SetConfig(config))?,
+        Method::Subscribe => *subscription = Some(telemetry.subscribe()),
+    }
+    Ok(Value::Bool(true))
+}
@@ -0,0 +1,134 @@
+// `inti-e3m ctl ...`, talks to a running instance over the control socket.
+
+use inti_e3m::config::Config;
+use inti_e3m::control::{socket_path, RemoteCommand, Request, Response};
+use serde::Serialize;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+const USAGE: &str = "usage: inti-e3m ctl <command>
+  home | pause | park | resume | halt
+  move <percent> [--ms <ms>]     move to a point on the stroke
+  raw <gcode>                    send a line of G-code as is
+  config                         print the running config
+  config set <file>              replace it, `-` reads stdin
+  watch                          print telemetry as it happens";
+
+#[derive(Debug, Error)]
+enum CtlError {
+    #[error("{0}\n{USAGE}")]
+    Usage(String),
+    #[error("could not reach inti-e3m at {0}, is it running with the control socket on?")]
+    Connect(String, #[source] std::io::Error),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error("{0} ({1})")]
+    Rpc(String, i64),
+    #[error("inti-e3m closed the connection")]
+    Closed,
+}
+
+/// Runs `ctl` if that's what the arguments ask for, and returns the exit code.
+pub async fn from_args() -> Option<i32> {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() != Some("ctl") { return None; }
+    match run(args.collect()).await {
+        Ok(()) => Some(0),
+        Err(e) => {
+            eprintln!("{e}");
+            Some(1)
+        }
+    }
+}
+
+async fn run(args: Vec<String>) -> Result<(), CtlError> {
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let command = match args.as_slice() {
+        ["home"] => RemoteCommand::Home,
+        ["pause"] => RemoteCommand::Pause,
+        ["park"] => RemoteCommand::Park,
+        ["resume"] => RemoteCommand::Resume,
+        ["halt"] => RemoteCommand::Halt,
+        ["move", position, rest @ ..] => {
+            let position = position.parse().map_err(|_| CtlError::Usage(format!("`{position}` isn't a percentage")))?;
+            let ms = match rest {
+                [] => None,
+                ["--ms", ms] => Some(ms.parse().map_err(|_| CtlError::Usage(format!("`{ms}` isn't a number of milliseconds")))?),
+                _ => return Err(CtlError::Usage(format!("unexpected `{}`", rest.join(" ")))),
+            };
+            RemoteCommand::Move { position, ms }
+        }
+        ["raw", line @ ..] if !line.is_empty() => RemoteCommand::Raw { line: line.join(" ") },
+        ["config"] => {
+            let config = Client::connect().await?.call("config.get", Value::Null).await?;
+            println!("{}", serde_json::to_string_pretty(&config)?);
+            return Ok(());
+        }
+        ["config", "set", file] => {
+            let json = match *file {
+                "-" => std::io::read_to_string(std::io::stdin())?,
+                file => std::fs::read_to_string(file)?,
+            };
+            let config: Config = serde_json::from_str(&json)?;
+            Client::connect().await?.call("config.set", config).await?;
+            return Ok(());
+        }
+        ["watch"] => return Client::connect().await?.watch().await,
+        [] => return Err(CtlError::Usage("no command given".to_string())),
+        _ => return Err(CtlError::Usage(format!("unknown command `{}`", args.join(" ")))),
+    };
+    Client::connect().await?.call("command", command).await?;
+    Ok(())
+}
+
+struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    write: OwnedWriteHalf,
+    next_id: u64,
+}
+
+impl Client {
+    async fn connect() -> Result<Self, CtlError> {
+        let path = socket_path();
+        let stream = UnixStream::connect(&path).await.map_err(|e| CtlError::Connect(path.display().to_string(), e))?;
+        let (read, write) = stream.into_split();
+        Ok(Client { lines: BufReader::new(read).lines(), write, next_id: 1 })
+    }
+
+    async fn call(&mut self, method: &str, params: impl Serialize) -> Result<Value, CtlError> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut json = serde_json::to_string(&Request::new(id, method, params))?;
+        json.push('\n');
+        self.write.write_all(json.as_bytes()).await?;
+        loop {
+            let response = self.next().await?;
+            // skip any telemetry that was already on its way
+            if response.id.as_ref().and_then(Value::as_u64) != Some(id) { continue; }
+            return match (response.result, response.error) {
+                (_, Some(error)) => Err(CtlError::Rpc(error.message, error.code)),
+                (result, None) => Ok(result.unwrap_or_default()),
+            };
+        }
+    }
+
+    async fn next(&mut self) -> Result<Response, CtlError> {
+        let line = self.lines.next_line().await?.ok_or(CtlError::Closed)?;
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    async fn watch(mut self) -> Result<(), CtlError> {
+        self.call("telemetry.subscribe", Value::Null).await?;
+        loop {
+            let notification = self.next().await?;
+            if let Some(params) = notification.params {
+                println!("{params}");
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use serde::Serialize;
+
+/// Which firmware family answered M115.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub enum FirmwareKind {
+    Marlin2,
+    Marlin1,
+    Klipper,
+    Unknown,
+}
+
+impl Display for FirmwareKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FirmwareKind::Marlin2 => write!(f, "Marlin 2.x"),
+            FirmwareKind::Marlin1 => write!(f, "Marlin 1.1"),
+            FirmwareKind::Klipper => write!(f, "Klipper"),
+            FirmwareKind::Unknown => write!(f, "Unknown"),
+        }
+    }
+}
+
+/// What the firmware told us about itself in its M115 reply.
+#[derive(Debug, Clone, Serialize)]
+pub struct FirmwareCapabilities {
+    /// `FIRMWARE_NAME` as reported, e.g. "Marlin bugfix-2.1.x (Jan  1 2024 12:00:00)"
+    pub firmware_name: String,
+    pub kind: FirmwareKind,
+    /// every `Cap:NAME:0/1` line
+    pub caps: BTreeMap<String, bool>,
+}
+
+impl FirmwareCapabilities {
+    /// Parse the lines of an M115 reply. Lines that aren't part of it (echo:, ok, ...) are skipped.
+    ///
+    /// Marlin: `FIRMWARE_NAME:Marlin 2.1.2 (Github) SOURCE_CODE_URL:... PROTOCOL_VERSION:1.0 ...`
+    /// followed by `Cap:AUTOREPORT_POS:1` lines. Klipper: `FIRMWARE_VERSION:v0.12 FIRMWARE_NAME:Klipper`.
+    pub fn parse<'a>(lines: impl IntoIterator<Item = &'a str>) -> Option<Self> {
+        let mut firmware_name = None;
+        let mut caps = BTreeMap::new();
+        for line in lines {
+            let line = line.trim();
+            if let Some(cap) = line.strip_prefix("Cap:") {
+                if let Some((name, value)) = cap.rsplit_once(':') {
+                    caps.insert(name.to_string(), value.trim() == "1");
+                }
+            } else if let Some(start) = line.find("FIRMWARE_NAME:") {
+                let rest = &line[start + "FIRMWARE_NAME:".len()..];
+                // the name runs until the next KEY: field
+                let end = rest.match_indices(' ')
+                    .map(|(i, _)| i)
+                    .find(|&i| rest[i + 1..].split_once(':').is_some_and(|(key, _)| is_field_key(key)))
+                    .unwrap_or(rest.len());
+                firmware_name = Some(rest[..end].trim().to_string());
+            }
+        }
+        let firmware_name = firmware_name?;
+        let kind = if firmware_name.starts_with("Klipper") {
+            FirmwareKind::Klipper
+        } else if firmware_name.starts_with("Marlin") && (firmware_name.contains(" 2.") || firmware_name.contains("-2.")) {
+            FirmwareKind::Marlin2
+        } else if firmware_name.starts_with("Marlin") {
+            FirmwareKind::Marlin1
+        } else {
+            FirmwareKind::Unknown
+        };
+        Some(FirmwareCapabilities { firmware_name, kind, caps })
+    }
+
+    pub fn has(&self, cap: &str) -> bool {
+        self.caps.get(cap).copied().unwrap_or(false)
+    }
+
+    /// M112 is acted on as soon as it arrives rather than after everything already queued.
+    pub fn emergency_parser(&self) -> bool {
+        self.has("EMERGENCY_PARSER")
+    }
+    pub fn autoreport_pos(&self) -> bool {
+        self.has("AUTOREPORT_POS")
+    }
+}
+
+fn is_field_key(key: &str) -> bool {
+    !key.is_empty() && key.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// A position report, from M114 or auto-reported with M154.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+    pub e: f32,
+}
+
+impl Position {
+    /// `X:120.00 Y:0.00 Z:0.00 E:0.00 Count X:9600 Y:0 Z:0`, only the part before `Count` is used.
+    /// Klipper puts it on the `ok` line instead.
+    pub fn parse(line: &str) -> Option<Position> {
+        let line = line.strip_prefix("ok").unwrap_or(line);
+        let line = line.split(" Count").next()?;
+        let mut axes = [None; 4];
+        for field in line.split_whitespace() {
+            let (axis, value) = field.split_once(':')?;
+            let i = match axis {
+                "X" => 0,
+                "Y" => 1,
+                "Z" => 2,
+                "E" => 3,
+                _ => return None,
+            };
+            axes[i] = Some(value.parse::<f32>().ok().filter(|v| v.is_finite())?);
+        }
+        Some(Position { x: axes[0]?, y: axes[1]?, z: axes[2]?, e: axes[3]? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// M115 from a stock Ender 3 V2 (Marlin 2.1.2.1), with a busy line that snuck in
+    const MARLIN_2: &str = "FIRMWARE_NAME:Marlin 2.1.2.1 (Feb  6 2023 12:00:00) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 MACHINE_TYPE:Ender-3 V2 EXTRUDER_COUNT:1 UUID:cede2a2f-41a2-4748-9b12-c55c62f367ff
+Cap:SERIAL_XON_XOFF:0
+Cap:BINARY_FILE_TRANSFER:0
+Cap:EEPROM:1
+Cap:VOLUMETRIC:1
+Cap:AUTOREPORT_POS:1
+Cap:AUTOREPORT_TEMP:1
+echo:busy: processing
+Cap:PROGRESS:0
+Cap:PRINT_JOB:1
+Cap:AUTOLEVEL:1
+Cap:EMERGENCY_PARSER:1
+Cap:HOST_ACTION_COMMANDS:0
+Cap:SDCARD:1
+Cap:THERMAL_PROTECTION:1
+Cap:ARCS:1";
+
+    /// M115 from a stock Ender 3 (Marlin 1.1.9)
+    const MARLIN_1: &str = "FIRMWARE_NAME:Marlin 1.1.9 (Github) SOURCE_CODE_URL:https://github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0 MACHINE_TYPE:Ender-3 EXTRUDER_COUNT:1 UUID:cede2a2f-41a2-4748-9b12-c55c62f367ff
+Cap:SERIAL_XON_XOFF:0
+Cap:EEPROM:1
+Cap:VOLUMETRIC:1
+Cap:AUTOREPORT_TEMP:1
+Cap:PROGRESS:0
+Cap:PRINT_JOB:1
+Cap:AUTOLEVEL:0
+Cap:Z_PROBE:0
+Cap:EMERGENCY_PARSER:0
+Cap:AUTOREPORT_SD_STATUS:0
+Cap:THERMAL_PROTECTION:1";
+
+    #[test]
+    fn marlin_2() {
+        let firmware = FirmwareCapabilities::parse(MARLIN_2.lines()).unwrap();
+        assert_eq!(firmware.firmware_name, "Marlin 2.1.2.1 (Feb  6 2023 12:00:00)");
+        assert_eq!(firmware.kind, FirmwareKind::Marlin2);
+        assert_eq!(firmware.caps.len(), 14);
+        assert!(firmware.emergency_parser());
+        assert!(firmware.autoreport_pos());
+        assert!(!firmware.has("ADVANCED_OK"));
+        assert!(!firmware.has("PROGRESS"));
+    }
+
+    #[test]
+    fn marlin_1() {
+        let firmware = FirmwareCapabilities::parse(MARLIN_1.lines()).unwrap();
+        assert_eq!(firmware.firmware_name, "Marlin 1.1.9 (Github)");
+        assert_eq!(firmware.kind, FirmwareKind::Marlin1);
+        assert!(!firmware.emergency_parser());
+        assert!(!firmware.autoreport_pos());
+        assert!(firmware.has("EEPROM"));
+    }
+
+    #[test]
+    fn marlin_bugfix() {
+        let firmware = FirmwareCapabilities::parse(["FIRMWARE_NAME:Marlin bugfix-2.1.x (Jan  1 2024 12:00:00) SOURCE_CODE_URL:github.com/MarlinFirmware/Marlin PROTOCOL_VERSION:1.0"]).unwrap();
+        assert_eq!(firmware.firmware_name, "Marlin bugfix-2.1.x (Jan  1 2024 12:00:00)");
+        assert_eq!(firmware.kind, FirmwareKind::Marlin2);
+        assert!(firmware.caps.is_empty());
+    }
+
+    #[test]
+    fn klipper() {
+        let firmware = FirmwareCapabilities::parse(["FIRMWARE_VERSION:v0.12.0-85-gd785b396 FIRMWARE_NAME:Klipper"]).unwrap();
+        assert_eq!(firmware.firmware_name, "Klipper");
+        assert_eq!(firmware.kind, FirmwareKind::Klipper);
+    }
+
+    #[test]
+    fn no_firmware_name() {
+        assert!(FirmwareCapabilities::parse(["echo:Unknown command: \"M115\"", "Cap:EEPROM:1"]).is_none());
+        assert!(FirmwareCapabilities::parse([]).is_none());
+    }
+
+    #[test]
+    fn position() {
+        let position = Position::parse("X:120.00 Y:0.00 Z:5.00 E:0.00 Count X:9600 Y:0 Z:2000").unwrap();
+        assert_eq!((position.x, position.y, position.z, position.e), (120.0, 0.0, 5.0, 0.0));
+        // klipper's is on the ok
+        assert_eq!(Position::parse("ok X:80.00 Y:0.00 Z:0.00 E:0.00").unwrap().x, 80.0);
+        assert!(Position::parse("X:120.00 Y:0.00").is_none());
+        assert!(Position::parse("echo:busy: processing").is_none());
+    }
+
+    #[test]
+    fn position_rejects_non_finite() {
+        assert!(Position::parse("X:nan Y:0.00 Z:0.00 E:0.00").is_none());
+        assert!(Position::parse("X:inf Y:0.00 Z:0.00 E:0.00").is_none());
+        assert!(Position::parse("X:120.00 Y:-inf Z:0.00 E:0.00").is_none());
+    }
+}
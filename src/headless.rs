@@ -0,0 +1,95 @@
+// no terminal, just run the active profile and log what happens. for a pi next to the printer.
+
+use tokio::sync::{broadcast, mpsc};
+use inti_e3m::config::Config;
+#[cfg(unix)]
+use inti_e3m::control::ControlSocket;
+use inti_e3m::control::ControlRequest;
+use inti_e3m::metrics::MetricsEndpoint;
+use inti_e3m::server::Server;
+use inti_e3m::telemetry::{ErrorKind, Telemetry};
+use inti_e3m::command::{QueuedCommand, Source};
+
+pub async fn run(mut config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let mut metrics = MetricsEndpoint::default();
+    metrics.update(&config.metrics);
+    let (telemetry, mut rx) = mpsc::unbounded_channel();
+    let mut server = Server::from_config(&config, telemetry);
+    // scripts can drive us too, `inti-e3m ctl`
+    let (broadcast, _) = broadcast::channel(256);
+    let (requests, mut control_rx) = mpsc::unbounded_channel();
+    #[cfg(unix)]
+    let mut control = ControlSocket::default();
+    #[cfg(unix)]
+    control.update(config.control_socket, &requests, &broadcast);
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {
+                log::info!("Stopping");
+                server.token.cancel();
+            }
+            result = &mut server.handle => {
+                // the pipeline might not get to it before we exit
+                server.recorder.stop();
+                log::info!("Sent {} commands, coalesced {} stale movements", server.queue_stats.delivered(), server.queue_stats.coalesced());
+                return Ok(result??);
+            }
+            Some(telemetry) = rx.recv() => {
+                // nobody listening is fine
+                let _ = broadcast.send(telemetry.clone());
+                log_telemetry(telemetry);
+            }
+            Some(request) = control_rx.recv() => {
+                if !control_request(request, &mut config, &server).await { continue; }
+                if let Err(e) = config.save() {
+                    log::error!("Could not save config: {}", e);
+                }
+                metrics.update(&config.metrics);
+                #[cfg(unix)]
+                control.update(config.control_socket, &requests, &broadcast);
+            }
+        }
+    }
+}
+
+/// Returns whether the config changed. Only what can change live is applied, the rest on the next start.
+async fn control_request(request: ControlRequest, config: &mut Config, server: &Server) -> bool {
+    match request {
+        ControlRequest::Command(command) => {
+            if let Err(e) = server.tx.send(QueuedCommand::new(Source::Remote, command)).await {
+                log::error!("Failure sending command to marlin. Error: \n {}", e)
+            }
+        }
+        ControlRequest::GetConfig(reply) => { let _ = reply.send(config.clone()); }
+        ControlRequest::SetConfig(mut new) => {
+            new.sanitize();
+            if let Err(e) = new.validate() {
+                log::error!("New config rejected: {}", e);
+                return false;
+            }
+            *config = *new;
+            server.stages.send_replace(config.profile().pipeline.clone());
+            server.recorder.update(&config.recording);
+            return true;
+        }
+    }
+    false
+}
+
+fn log_telemetry(telemetry: Telemetry) {
+    match telemetry {
+        Telemetry::InputStatus(status) => log::info!("Input: {:?}", status),
+        Telemetry::BackendStatus(status) => log::info!("Printer: {:?}", status),
+        Telemetry::GCode(gcode) => log::trace!("> {} ({})", gcode.line, gcode.source),
+        Telemetry::GCodeReply(id, reply) if !reply.is_empty() => log::debug!("< {} (line {})", reply, id),
+        Telemetry::Latency(latency) => log::trace!("Acknowledged after {:?} queued, {:?} at the printer", latency.queued, latency.printer),
+        Telemetry::Firmware(Some(firmware)) => log::info!("Firmware: {} ({})", firmware.firmware_name, firmware.kind),
+        Telemetry::Error(ErrorKind::Input(report) | ErrorKind::Backend(report)) => {
+            log::error!("[{}] {}", report.code, report.chain.join(": "));
+            if let Some(hint) = report.hint {
+                log::error!("Try: {}", hint);
+            }
+        }
+        _ => {}
+    }
+}
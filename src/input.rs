@@ -0,0 +1,23 @@
+use std::error::Error as StdError;
+use tokio::sync::mpsc::Sender;
+use tokio_util::sync::CancellationToken;
+use crate::config::RestartPolicy;
+use crate::telemetry::{Diagnose, TelemetrySender};
+use crate::command::QueuedCommand;
+
+/// Something that produces commands, e.g. Intiface or XToys.
+pub trait InputSource: Send + 'static {
+    type Error: StdError + Diagnose + Send + Sync + 'static;
+
+    /// for logs, e.g. "Intiface"
+    fn name(&self) -> &'static str;
+
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::OnFailure
+    }
+
+    /// Send commands into `tx` until the token is cancelled or the other end goes away.
+    /// Called again by the supervisor if it gets restarted.
+    fn run(&mut self, tx: Sender<QueuedCommand>, telemetry: TelemetrySender, token: CancellationToken)
+        -> impl Future<Output = Result<(), Self::Error>> + Send;
+}
@@ -0,0 +1,26 @@
+//! Streams Intiface/XToys strokes to a 3D printer as G-code.
+//!
+//! An [`input::InputSource`] turns whatever the other end sends into [`Command`]s, the
+//! profile's [`pipeline::Pipeline`] filters them, and a [`backend::MotionBackend`] carries them out.
+//! A [`server::Server`] runs all of it. What it's up to comes back as [`telemetry::Telemetry`].
+//! Scripts can drive a running instance through [`control`], and a session can be kept with
+//! [`recorder`].
+
+pub mod backend;
+pub mod command;
+pub mod config;
+pub mod control;
+pub mod firmware;
+pub mod input;
+pub mod metrics;
+pub mod pipeline;
+pub mod queue;
+pub mod recorder;
+pub mod server;
+pub mod telemetry;
+pub mod usb;
+pub mod websocket;
+mod extoy_de;
+mod tcode_de;
+
+pub use command::{Command, QueuedCommand, Source};
@@ -1,29 +1,75 @@
 #![feature(if_let_guard)]
 extern crate core;
 
-use crate::tui::app::App;
-use crate::usb::Command;
-use config::Config;
+use inti_e3m::config::Config;
 
-mod tcode_de;
+#[cfg(feature = "tui")]
 mod tui;
-mod websocket;
-mod usb;
-mod server;
-pub(crate) mod config;
-mod extoy_de;
+#[cfg(not(feature = "tui"))]
+mod headless;
+#[cfg(unix)]
+mod ctl;
 
+#[cfg(feature = "tui")]
 #[tokio::main]
 async fn main() -> color_eyre::Result<()> {
+    #[cfg(unix)]
+    if let Some(code) = ctl::from_args().await { std::process::exit(code); }
     // Set max_log_level to Trace
     tui_logger::init_logger(log::LevelFilter::Debug).unwrap();
     // Set default level for unknown targets to Trace
     tui_logger::set_default_level(log::LevelFilter::Trace);
     color_eyre::install()?;
+    #[cfg(feature = "tracing")]
+    init_tracing();
+    let config = load_config();
     let terminal = ratatui::init();
-    let result = App::using_config(Config::default()).run(terminal).await;
+    let result = tui::app::App::using_config(config).run(terminal).await;
     ratatui::restore();
     result
 }
 
+#[cfg(not(feature = "tui"))]
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    if let Some(code) = ctl::from_args().await { std::process::exit(code); }
+    simple_logger::SimpleLogger::new()
+        .with_level(log::LevelFilter::Info)
+        .env()
+        .init()?;
+    #[cfg(feature = "tracing")]
+    init_tracing();
+    headless::run(load_config()).await
+}
 
+fn load_config() -> Config {
+    Config::load().unwrap_or_else(|e| {
+        log::error!("Could not load config, using defaults: {}", e);
+        // or the first change would save over it
+        match Config::back_up() {
+            Ok(backup) => log::warn!("Moved the old config to {}", backup.display()),
+            Err(e) => log::error!("Could not back up the old config: {}", e),
+        }
+        Config::default()
+    })
+}
+
+/// Writes command spans to the file in `$INTI_E3M_TRACE`, if it's set.
+#[cfg(feature = "tracing")]
+fn init_tracing() {
+    let Some(path) = std::env::var_os("INTI_E3M_TRACE") else { return };
+    let file = match std::fs::File::create(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("Could not open trace file {}: {}", path.display(), e);
+            return;
+        }
+    };
+    tracing_subscriber::fmt()
+        .with_writer(std::sync::Mutex::new(file))
+        .with_ansi(false)
+        .with_max_level(tracing::Level::DEBUG)
+        .with_span_events(tracing_subscriber::fmt::format::FmtSpan::CLOSE)
+        .init();
+}
@@ -0,0 +1,278 @@
+//! Counters for graphing a session, served in the Prometheus text format.
+//!
+//! Everything is counted into [`METRICS`] as it happens, whether or not anything is scraping.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use crate::config::MetricsConfig;
+use crate::command::Source;
+
+pub static METRICS: Metrics = Metrics::new();
+
+/// Upper bounds of the latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 11] = [0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Fixed buckets, see [`LATENCY_BUCKETS`].
+#[derive(Debug)]
+pub struct Histogram {
+    /// not cumulative, that's done when rendering
+    buckets: [Counter; LATENCY_BUCKETS.len()],
+    sum_micros: Counter,
+    count: Counter,
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Histogram { buckets: [const { Counter::new() }; LATENCY_BUCKETS.len()], sum_micros: Counter::new(), count: Counter::new() }
+    }
+
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[bucket].inc();
+        }
+        self.sum_micros.add(duration.as_micros() as u64);
+        self.count.inc();
+    }
+}
+
+#[derive(Debug)]
+pub struct Metrics {
+    /// indexed like [`Source::ALL`]
+    commands_received: [Counter; Source::ALL.len()],
+    pub gcode_lines: Counter,
+    pub bytes_written: Counter,
+    /// moves slowed down to the max feedrate
+    pub limited: Counter,
+    pub coalesced: Counter,
+    pub dropped_paused: Counter,
+    pub dropped_disconnected: Counter,
+    /// queued before a halt that skipped ahead of them
+    pub dropped_halted: Counter,
+    /// dropped by a pipeline stage, e.g. the dead band
+    pub dropped_filtered: Counter,
+    pub websocket_connects: Counter,
+    pub input_restarts: Counter,
+    pub backend_restarts: Counter,
+    pub serial_errors: Counter,
+    /// from the input receiving a move to its G-code being written
+    pub input_to_serial: Histogram,
+    /// from writing a move to the printer's `ok`
+    pub serial_to_ok: Histogram,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            commands_received: [const { Counter::new() }; Source::ALL.len()],
+            gcode_lines: Counter::new(),
+            bytes_written: Counter::new(),
+            limited: Counter::new(),
+            coalesced: Counter::new(),
+            dropped_paused: Counter::new(),
+            dropped_disconnected: Counter::new(),
+            dropped_halted: Counter::new(),
+            dropped_filtered: Counter::new(),
+            websocket_connects: Counter::new(),
+            input_restarts: Counter::new(),
+            backend_restarts: Counter::new(),
+            serial_errors: Counter::new(),
+            input_to_serial: Histogram::new(),
+            serial_to_ok: Histogram::new(),
+        }
+    }
+
+    pub fn command_received(&self, source: Source) {
+        if let Some(i) = Source::ALL.iter().position(|&s| s == source) {
+            self.commands_received[i].inc();
+        }
+    }
+
+    /// The Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let by_source = Source::ALL.iter().zip(&self.commands_received).map(|(source, count)| (format!("{{source=\"{source}\"}}"), count.get()));
+        counter(&mut out, "inti_commands_received_total", "Commands received, by where they came from.", by_source);
+        counter(&mut out, "inti_gcode_lines_total", "Lines of G-code written to the printer.", [(String::new(), self.gcode_lines.get())]);
+        counter(&mut out, "inti_serial_bytes_written_total", "Bytes written to the serial port.", [(String::new(), self.bytes_written.get())]);
+        counter(&mut out, "inti_limited_total", "Moves slowed down to the max feedrate.", [(String::new(), self.limited.get())]);
+        counter(&mut out, "inti_coalesced_total", "Movements skipped because a newer one was waiting.", [(String::new(), self.coalesced.get())]);
+        counter(&mut out, "inti_dropped_total", "Movements never sent.", [
+            ("{reason=\"paused\"}".to_string(), self.dropped_paused.get()),
+            ("{reason=\"disconnected\"}".to_string(), self.dropped_disconnected.get()),
+            ("{reason=\"halted\"}".to_string(), self.dropped_halted.get()),
+            ("{reason=\"filtered\"}".to_string(), self.dropped_filtered.get()),
+        ]);
+        counter(&mut out, "inti_websocket_connects_total", "Websocket connections made or accepted.", [(String::new(), self.websocket_connects.get())]);
+        counter(&mut out, "inti_restarts_total", "Times a side of the server was restarted after stopping.", [
+            ("{side=\"input\"}".to_string(), self.input_restarts.get()),
+            ("{side=\"backend\"}".to_string(), self.backend_restarts.get()),
+        ]);
+        counter(&mut out, "inti_serial_errors_total", "Serial port errors, including the printer going away.", [(String::new(), self.serial_errors.get())]);
+
+        histogram(&mut out, "inti_input_to_serial_seconds", "Time from receiving a move to writing its G-code.", &self.input_to_serial);
+        histogram(&mut out, "inti_serial_to_ok_seconds", "Time from writing a move to the printer acknowledging it.", &self.serial_to_ok);
+        out
+    }
+}
+
+/// `values` are the label set (`{a="b"}` or nothing) and the count.
+fn counter(out: &mut String, name: &str, help: &str, values: impl IntoIterator<Item = (String, u64)>) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} counter");
+    for (labels, value) in values {
+        let _ = writeln!(out, "{name}{labels} {value}");
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    let _ = writeln!(out, "# HELP {name} {help}\n# TYPE {name} histogram");
+    let mut cumulative = 0;
+    for (le, bucket) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+        cumulative += bucket.get();
+        let _ = writeln!(out, "{name}_bucket{{le=\"{le}\"}} {cumulative}");
+    }
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", histogram.count.get());
+    let _ = writeln!(out, "{name}_sum {}", histogram.sum_micros.get() as f64 / 1e6);
+    let _ = writeln!(out, "{name}_count {}", histogram.count.get());
+}
+
+/// The `/metrics` listener, restarted whenever its config changes.
+#[derive(Debug, Default)]
+pub struct MetricsEndpoint {
+    running: Option<(MetricsConfig, JoinHandle<()>)>,
+}
+
+impl MetricsEndpoint {
+    pub fn update(&mut self, config: &MetricsConfig) {
+        if self.running.as_ref().is_some_and(|(running, _)| running == config) { return; }
+        if let Some((_, handle)) = self.running.take() {
+            handle.abort();
+            log::info!("Metrics endpoint stopped");
+        }
+        if config.enabled {
+            self.running = Some((config.clone(), tokio::spawn(serve(config.bind.clone()))));
+        }
+    }
+}
+
+async fn serve(bind: String) {
+    let listener = match TcpListener::bind(&bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Metrics endpoint could not bind to {bind}: {e}");
+            return;
+        }
+    };
+    log::info!("Metrics on http://{bind}/metrics");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => { tokio::spawn(respond(stream)); }
+            Err(e) => log::warn!("Metrics endpoint could not accept a connection: {e}"),
+        }
+    }
+}
+
+/// Just enough HTTP for a scraper: `GET /metrics` gets the metrics, anything else a 404.
+async fn respond(mut stream: TcpStream) {
+    let mut request = [0u8; 1024];
+    let Ok(read) = stream.read(&mut request).await else { return };
+    let request = String::from_utf8_lossy(&request[..read]);
+    let response = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let body = METRICS.render();
+            format!("HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len())
+        }
+        _ => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string(),
+    };
+    let _ = stream.write_all(response.as_bytes()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(rendered: &str, prefix: &str) -> Vec<String> {
+        rendered.lines().filter(|line| line.starts_with(prefix)).map(str::to_string).collect()
+    }
+
+    #[test]
+    fn every_metric_has_a_type() {
+        let rendered = Metrics::new().render();
+        let types = lines(&rendered, "# TYPE ");
+        assert_eq!(types.len(), 11);
+        assert_eq!(lines(&rendered, "# HELP ").len(), types.len());
+        assert!(types.contains(&"# TYPE inti_commands_received_total counter".to_string()));
+        assert!(types.contains(&"# TYPE inti_serial_to_ok_seconds histogram".to_string()));
+        // every sample belongs to the metric typed above it
+        let mut current = "";
+        for line in rendered.lines() {
+            if let Some(typed) = line.strip_prefix("# TYPE ") {
+                current = typed.split(' ').next().unwrap();
+            } else if !line.starts_with('#') {
+                assert!(line.starts_with(current), "{line} after the TYPE of {current}");
+            }
+        }
+    }
+
+    #[test]
+    fn received_by_source() {
+        let metrics = Metrics::new();
+        metrics.command_received(Source::XToys);
+        metrics.command_received(Source::XToys);
+        metrics.command_received(Source::Remote);
+        assert_eq!(lines(&metrics.render(), "inti_commands_received_total"), [
+            "inti_commands_received_total{source=\"Intiface\"} 0",
+            "inti_commands_received_total{source=\"XToys\"} 2",
+            "inti_commands_received_total{source=\"Keyboard\"} 0",
+            "inti_commands_received_total{source=\"Console\"} 0",
+            "inti_commands_received_total{source=\"Remote\"} 1",
+            "inti_commands_received_total{source=\"Internal\"} 0",
+        ]);
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let metrics = Metrics::new();
+        for ms in [2, 2, 40, 3000] {
+            metrics.serial_to_ok.observe(Duration::from_millis(ms));
+        }
+        let rendered = metrics.render();
+        let bucket = |le: &str| format!("inti_serial_to_ok_seconds_bucket{{le=\"{le}\"}}");
+        let buckets = lines(&rendered, "inti_serial_to_ok_seconds_bucket");
+        assert_eq!(buckets.len(), LATENCY_BUCKETS.len() + 1);
+        assert!(buckets.contains(&format!("{} 0", bucket("0.001"))));
+        assert!(buckets.contains(&format!("{} 2", bucket("0.0025"))));
+        assert!(buckets.contains(&format!("{} 3", bucket("0.05"))));
+        assert!(buckets.contains(&format!("{} 3", bucket("2.5"))));
+        assert!(buckets.contains(&format!("{} 4", bucket("+Inf"))));
+        let counts: Vec<u64> = buckets.iter().map(|line| line.rsplit(' ').next().unwrap().parse().unwrap()).collect();
+        assert!(counts.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(lines(&rendered, "inti_serial_to_ok_seconds_sum"), ["inti_serial_to_ok_seconds_sum 3.044"]);
+        assert_eq!(lines(&rendered, "inti_serial_to_ok_seconds_count"), ["inti_serial_to_ok_seconds_count 4"]);
+    }
+}
@@ -0,0 +1,357 @@
+//! Filters movement goes through between the input and the printer, set up per profile.
+//!
+//! Only [`Command::Movement`] is touched, everything else (homing, halting...) passes straight
+//! through in order.
+
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+use crate::metrics::METRICS;
+use crate::recorder::{RecordPoint, Recorder};
+use crate::command::{Command, LinearAction, LinearModifier, QueuedCommand};
+
+/// One step of the pipeline as saved in the config. Percentages are of the whole stroke.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Stage {
+    /// squeeze the full stroke into `min`..`max` percent
+    Remap { min: f32, max: f32 },
+    /// top becomes bottom
+    Invert,
+    /// scale the distance from the middle of the stroke, 0.5 is half as far either way
+    Intensity(f32),
+    /// multiply move durations, 0.5 is twice as fast
+    TimeScale(f32),
+    /// how much faster, on top of any `TimeScale`, 2 halves every duration. the web UI's slider.
+    Speed(f32),
+    /// ignore moves closer than this many percent to the last one
+    DeadBand(f32),
+    /// only go this fraction of the way to each new position, 1 is off
+    Smooth(f32),
+    /// at most this many moves a second, extra ones are dropped
+    RateLimit(f32),
+}
+
+impl Stage {
+    pub fn build(&self) -> Box<dyn Filter> {
+        match *self {
+            Stage::Remap { min, max } => Box::new(Remap { min: min / 100.0, max: max / 100.0 }),
+            Stage::Invert => Box::new(Invert),
+            Stage::Intensity(factor) => Box::new(Intensity(factor)),
+            Stage::TimeScale(factor) => Box::new(TimeScale(factor)),
+            Stage::Speed(factor) => Box::new(TimeScale(1.0 / factor.max(0.01))),
+            Stage::DeadBand(percent) => Box::new(DeadBand { width: percent / 100.0, last: None }),
+            Stage::Smooth(alpha) => Box::new(Smooth { alpha: alpha.clamp(0.0, 1.0), last: None }),
+            Stage::RateLimit(per_second) => Box::new(RateLimit { interval: Duration::from_secs_f32(1.0 / per_second.max(0.01)), last: None }),
+        }
+    }
+}
+
+/// `remap:10-90`, `invert`, `intensity:0.8`, `time:0.2`, `speed:1.5`, `deadband:2`, `smooth:0.5`, `rate:20`
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Remap { min, max } => write!(f, "remap:{min}-{max}"),
+            Stage::Invert => write!(f, "invert"),
+            Stage::Intensity(factor) => write!(f, "intensity:{factor}"),
+            Stage::TimeScale(factor) => write!(f, "time:{factor}"),
+            Stage::Speed(factor) => write!(f, "speed:{factor}"),
+            Stage::DeadBand(percent) => write!(f, "deadband:{percent}"),
+            Stage::Smooth(alpha) => write!(f, "smooth:{alpha}"),
+            Stage::RateLimit(per_second) => write!(f, "rate:{per_second}"),
+        }
+    }
+}
+
+impl FromStr for Stage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, arg) = s.trim().split_once(':').unwrap_or((s.trim(), ""));
+        // NaN and inf parse fine but would end up in the G-code
+        let number = |arg: &str| arg.trim().parse::<f32>().ok()
+            .filter(|n| n.is_finite())
+            .ok_or_else(|| format!("`{name}` needs a number, got `{arg}`"));
+        Ok(match name {
+            "remap" => {
+                let (min, max) = arg.split_once('-').ok_or_else(|| format!("remap needs min-max, got `{arg}`"))?;
+                Stage::Remap { min: number(min)?, max: number(max)? }
+            }
+            "invert" => Stage::Invert,
+            "intensity" => Stage::Intensity(number(arg)?),
+            "time" => Stage::TimeScale(number(arg)?),
+            "speed" => Stage::Speed(number(arg)?),
+            "deadband" => Stage::DeadBand(number(arg)?),
+            "smooth" => Stage::Smooth(number(arg)?),
+            "rate" => Stage::RateLimit(number(arg)?),
+            _ => return Err(format!("Unknown stage `{name}`")),
+        })
+    }
+}
+
+/// Parse a comma separated list of stages, e.g. `invert, time:0.5`. `none` is no stages.
+pub fn parse_stages(s: &str) -> Result<Vec<Stage>, String> {
+    if s.trim().eq_ignore_ascii_case("none") { return Ok(vec![]); }
+    s.split(',').filter(|s| !s.trim().is_empty()).map(str::parse).collect()
+}
+
+pub fn stages_to_string(stages: &[Stage]) -> String {
+    if stages.is_empty() { return "none".to_string(); }
+    stages.iter().map(Stage::to_string).collect::<Vec<_>>().join(", ")
+}
+
+/// Something that changes or drops movement.
+pub trait Filter: Send {
+    /// `None` drops the move.
+    fn apply(&mut self, action: LinearAction, now: Instant) -> Option<LinearAction>;
+}
+
+struct Remap { min: f32, max: f32 }
+
+impl Filter for Remap {
+    fn apply(&mut self, mut action: LinearAction, _: Instant) -> Option<LinearAction> {
+        action.position = self.min + action.position * (self.max - self.min);
+        Some(action)
+    }
+}
+
+struct Invert;
+
+impl Filter for Invert {
+    fn apply(&mut self, mut action: LinearAction, _: Instant) -> Option<LinearAction> {
+        action.position = 1.0 - action.position;
+        Some(action)
+    }
+}
+
+struct Intensity(f32);
+
+impl Filter for Intensity {
+    fn apply(&mut self, mut action: LinearAction, _: Instant) -> Option<LinearAction> {
+        action.position = (0.5 + (action.position - 0.5) * self.0).clamp(0.0, 1.0);
+        Some(action)
+    }
+}
+
+struct TimeScale(f32);
+
+impl Filter for TimeScale {
+    fn apply(&mut self, mut action: LinearAction, _: Instant) -> Option<LinearAction> {
+        action.modifier = match action.modifier {
+            Some(LinearModifier::TIME(ms)) => Some(LinearModifier::TIME((ms as f32 * self.0) as u32)),
+            // same distance in a different time is the inverse for speed
+            Some(LinearModifier::SPEED(speed)) if self.0 > 0.0 => Some(LinearModifier::SPEED((speed as f32 / self.0) as u32)),
+            modifier => modifier,
+        };
+        Some(action)
+    }
+}
+
+struct DeadBand { width: f32, last: Option<f32> }
+
+impl Filter for DeadBand {
+    fn apply(&mut self, action: LinearAction, _: Instant) -> Option<LinearAction> {
+        if self.last.is_some_and(|last| (action.position - last).abs() < self.width) { return None; }
+        self.last = Some(action.position);
+        Some(action)
+    }
+}
+
+struct Smooth { alpha: f32, last: Option<f32> }
+
+impl Filter for Smooth {
+    fn apply(&mut self, mut action: LinearAction, _: Instant) -> Option<LinearAction> {
+        if let Some(last) = self.last {
+            action.position = last + (action.position - last) * self.alpha;
+        }
+        self.last = Some(action.position);
+        Some(action)
+    }
+}
+
+struct RateLimit { interval: Duration, last: Option<Instant> }
+
+impl Filter for RateLimit {
+    fn apply(&mut self, action: LinearAction, now: Instant) -> Option<LinearAction> {
+        if self.last.is_some_and(|last| now.duration_since(last) < self.interval) { return None; }
+        self.last = Some(now);
+        Some(action)
+    }
+}
+
+/// The stages of a profile, in order.
+pub struct Pipeline {
+    filters: Vec<Box<dyn Filter>>,
+}
+
+impl Pipeline {
+    pub fn new(stages: &[Stage]) -> Self {
+        Pipeline { filters: stages.iter().map(Stage::build).collect() }
+    }
+
+    pub fn process(&mut self, mut queued: QueuedCommand, now: Instant) -> Option<QueuedCommand> {
+        let Command::Movement(action) = &queued.command else { return Some(queued) };
+        let action = self.filters.iter_mut().try_fold(action.clone(), |action, filter| filter.apply(action, now))?;
+        queued.command = Command::Movement(action);
+        Some(queued)
+    }
+
+    /// Pass commands from `rx` to `tx` until either side goes away or the token is cancelled.
+    /// New stages sent on `stages` take over straight away (starting from a clean state).
+    /// Movement is handed to `recorder` on the way, which is saved once this stops.
+    pub async fn run(mut self, mut rx: Receiver<QueuedCommand>, tx: Sender<QueuedCommand>, mut stages: watch::Receiver<Vec<Stage>>, recorder: Recorder, token: CancellationToken) {
+        loop {
+            let queued = tokio::select! {
+                _ = token.cancelled() => break,
+                Ok(()) = stages.changed() => {
+                    let stages = stages.borrow_and_update();
+                    log::info!("Pipeline is now: {}", stages_to_string(&stages));
+                    self = Pipeline::new(&stages);
+                    continue;
+                }
+                queued = rx.recv() => match queued {
+                    Some(queued) => queued,
+                    None => break,
+                },
+            };
+            recorder.record(RecordPoint::BeforeFilters, &queued);
+            match self.process(queued, Instant::now()) {
+                Some(queued) => {
+                    recorder.record(RecordPoint::AfterFilters, &queued);
+                    if tx.send(queued).await.is_err() { break }
+                }
+                None => {
+                    log::trace!("Movement dropped by the pipeline");
+                    METRICS.dropped_filtered.inc();
+                }
+            }
+        }
+        recorder.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::command::Action;
+    use super::*;
+
+    fn movement(position: f32, modifier: Option<LinearModifier>) -> LinearAction {
+        LinearAction { action: Action::MOVE, id: 0, position, modifier }
+    }
+
+    /// Positions out of `stage` for moves at `positions`, all at the same instant.
+    fn positions(stage: Stage, positions: &[f32]) -> Vec<Option<f32>> {
+        let mut filter = stage.build();
+        let now = Instant::now();
+        positions.iter().map(|&p| filter.apply(movement(p, None), now).map(|a| a.position)).collect()
+    }
+
+    fn assert_close(actual: Vec<Option<f32>>, expected: &[Option<f32>]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            match (a, e) {
+                (Some(a), Some(e)) => assert!((a - e).abs() < 1e-5, "{actual:?} != {expected:?}"),
+                _ => assert_eq!(a, e, "{actual:?} != {expected:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn remap_squeezes_the_stroke() {
+        assert_close(positions(Stage::Remap { min: 20.0, max: 60.0 }, &[0.0, 0.5, 1.0]), &[Some(0.2), Some(0.4), Some(0.6)]);
+    }
+
+    #[test]
+    fn invert_flips_top_and_bottom() {
+        assert_close(positions(Stage::Invert, &[0.0, 0.25, 1.0]), &[Some(1.0), Some(0.75), Some(0.0)]);
+    }
+
+    #[test]
+    fn intensity_scales_around_the_middle() {
+        assert_close(positions(Stage::Intensity(0.5), &[0.0, 0.5, 1.0]), &[Some(0.25), Some(0.5), Some(0.75)]);
+        // can't go past the ends
+        assert_close(positions(Stage::Intensity(3.0), &[0.0, 1.0]), &[Some(0.0), Some(1.0)]);
+    }
+
+    #[test]
+    fn time_scale_changes_durations_and_speeds() {
+        let mut filter = Stage::TimeScale(0.5).build();
+        let now = Instant::now();
+        let timed = filter.apply(movement(0.5, Some(LinearModifier::TIME(400))), now).unwrap();
+        assert!(matches!(timed.modifier, Some(LinearModifier::TIME(200))));
+        let speed = filter.apply(movement(0.5, Some(LinearModifier::SPEED(10))), now).unwrap();
+        assert!(matches!(speed.modifier, Some(LinearModifier::SPEED(20))));
+        let none = filter.apply(movement(0.5, None), now).unwrap();
+        assert!(none.modifier.is_none());
+    }
+
+    #[test]
+    fn dead_band_drops_small_moves() {
+        assert_close(positions(Stage::DeadBand(5.0), &[0.5, 0.52, 0.56, 0.58, 0.2]), &[Some(0.5), None, Some(0.56), None, Some(0.2)]);
+    }
+
+    #[test]
+    fn smooth_goes_part_of_the_way() {
+        assert_close(positions(Stage::Smooth(0.5), &[0.0, 1.0, 1.0]), &[Some(0.0), Some(0.5), Some(0.75)]);
+        assert_close(positions(Stage::Smooth(1.0), &[0.0, 1.0]), &[Some(0.0), Some(1.0)]);
+    }
+
+    #[test]
+    fn rate_limit_drops_moves_that_come_too_soon() {
+        let mut filter = Stage::RateLimit(10.0).build();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert!(filter.apply(movement(0.1, None), at(0)).is_some());
+        assert!(filter.apply(movement(0.2, None), at(50)).is_none());
+        assert!(filter.apply(movement(0.3, None), at(110)).is_some());
+        assert!(filter.apply(movement(0.4, None), at(160)).is_none());
+    }
+
+    #[test]
+    fn parse_stages_round_trips() {
+        let stages = parse_stages("remap:10-90, invert, intensity:0.8, time:0.5, speed:1.5, deadband:2, smooth:0.5, rate:20").unwrap();
+        assert_eq!(stages, vec![
+            Stage::Remap { min: 10.0, max: 90.0 },
+            Stage::Invert,
+            Stage::Intensity(0.8),
+            Stage::TimeScale(0.5),
+            Stage::Speed(1.5),
+            Stage::DeadBand(2.0),
+            Stage::Smooth(0.5),
+            Stage::RateLimit(20.0),
+        ]);
+        assert_eq!(parse_stages(&stages_to_string(&stages)).unwrap(), stages);
+        assert_eq!(parse_stages("none").unwrap(), vec![]);
+        assert_eq!(parse_stages("").unwrap(), vec![]);
+        assert_eq!(stages_to_string(&[]), "none");
+    }
+
+    #[test]
+    fn parse_stages_rejects_bad_input() {
+        for bad in ["wobble", "intensity", "intensity:abc", "remap:10", "smooth:NaN", "intensity:inf", "rate:-inf", "remap:0-NaN"] {
+            assert!(parse_stages(bad).is_err(), "{bad} should be rejected");
+        }
+    }
+
+    #[test]
+    fn speed_stacks_on_time_scale() {
+        // xtoys' compensation stays, the speed goes on top
+        let mut pipeline = Pipeline::new(&[Stage::TimeScale(0.2), Stage::Speed(2.0)]);
+        let queued = QueuedCommand::new(crate::command::Source::Internal, Command::Movement(movement(0.5, Some(LinearModifier::TIME(1000)))));
+        let moved = pipeline.process(queued, Instant::now()).unwrap();
+        assert!(matches!(moved.command, Command::Movement(LinearAction { modifier: Some(LinearModifier::TIME(100)), .. })));
+    }
+
+    #[test]
+    fn pipeline_only_touches_movement() {
+        let mut pipeline = Pipeline::new(&[Stage::Invert]);
+        let now = Instant::now();
+        let moved = pipeline.process(QueuedCommand::new(crate::command::Source::Internal, Command::Movement(movement(0.2, None))), now).unwrap();
+        assert!(matches!(moved.command, Command::Movement(LinearAction { position, .. }) if (position - 0.8).abs() < 1e-5));
+        let home = pipeline.process(QueuedCommand::new(crate::command::Source::Internal, Command::Home), now).unwrap();
+        assert!(matches!(home.command, Command::Home));
+    }
+}
@@ -0,0 +1,194 @@
+//! The queue between the pipeline and the printer.
+//!
+//! If the printer falls behind, a plain channel fills up with positions that are already out of
+//! date and the machine ends up seconds behind the video. With [`QueuePolicy::LatestWins`] a run
+//! of queued movements is collapsed into the newest one. Everything else (home, halt, pause...)
+//! is never dropped and keeps its place in line, except a halt can skip ahead while the backend
+//! is waiting on the printer. When it does, whatever it skipped is thrown away, it was asked for
+//! before the halt and running it afterwards would undo the halt.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::Receiver;
+use crate::config::choice;
+use crate::metrics::METRICS;
+use crate::command::{Command, QueuedCommand};
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub enum QueuePolicy {
+    /// every movement gets sent, in order
+    Fifo,
+    /// skip to the newest movement when several are waiting
+    #[default]
+    LatestWins,
+}
+
+choice!(QueuePolicy, "queue policy", [Fifo, LatestWins]);
+
+/// Counters, shared with whoever wants to show them.
+#[derive(Debug, Default)]
+pub struct QueueStats {
+    /// handed to the backend
+    delivered: AtomicU64,
+    /// movements skipped because a newer one was already waiting
+    coalesced: AtomicU64,
+}
+
+impl QueueStats {
+    pub fn delivered(&self) -> u64 {
+        self.delivered.load(Ordering::Relaxed)
+    }
+    pub fn coalesced(&self) -> u64 {
+        self.coalesced.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+pub struct CommandQueue {
+    rx: Receiver<QueuedCommand>,
+    policy: QueuePolicy,
+    /// taken off `rx` but not handed out yet, already coalesced
+    backlog: VecDeque<QueuedCommand>,
+    stats: Arc<QueueStats>,
+}
+
+impl CommandQueue {
+    pub fn new(rx: Receiver<QueuedCommand>, policy: QueuePolicy) -> Self {
+        CommandQueue { rx, policy, backlog: VecDeque::new(), stats: Arc::default() }
+    }
+
+    pub fn stats(&self) -> Arc<QueueStats> {
+        self.stats.clone()
+    }
+
+    /// The next command, `None` once every sender is gone. Cancel safe, so it can go in a `select!`.
+    pub async fn recv(&mut self) -> Option<QueuedCommand> {
+        if self.backlog.is_empty() {
+            let next = self.rx.recv().await?;
+            self.push(next);
+        }
+        while let Ok(queued) = self.rx.try_recv() {
+            self.push(queued);
+        }
+        let next = self.backlog.pop_front()?;
+        self.stats.delivered.fetch_add(1, Ordering::Relaxed);
+        Some(next)
+    }
+
+    /// For while the backend can't take anything else: wait for a [`Command::Halt`], which skips
+    /// the line and cancels the backlog, and keep everything else for [`recv`](Self::recv). Also
+    /// cancel safe.
+    pub async fn recv_halt(&mut self) -> Option<QueuedCommand> {
+        loop {
+            let queued = self.rx.recv().await?;
+            if let Command::Halt = queued.command {
+                for cancelled in self.backlog.drain(..) {
+                    match cancelled.command {
+                        Command::Movement(_) => METRICS.dropped_halted.inc(),
+                        command => log::info!("Halted before {:?} could be sent", command),
+                    }
+                }
+                self.stats.delivered.fetch_add(1, Ordering::Relaxed);
+                return Some(queued);
+            }
+            self.push(queued);
+        }
+    }
+
+    /// Add to the backlog, replacing a movement at the back of it if the policy says so.
+    fn push(&mut self, queued: QueuedCommand) {
+        let replaces = self.policy == QueuePolicy::LatestWins
+            && matches!(queued.command, Command::Movement(_))
+            && matches!(self.backlog.back(), Some(QueuedCommand { command: Command::Movement(_), .. }));
+        if replaces {
+            self.backlog.pop_back();
+            self.stats.coalesced.fetch_add(1, Ordering::Relaxed);
+            METRICS.coalesced.inc();
+        }
+        self.backlog.push_back(queued);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use crate::command::{Action, LinearAction, Source};
+    use super::*;
+
+    fn movement(position: f32) -> Command {
+        Command::Movement(LinearAction { action: Action::MOVE, id: 0, position, modifier: None })
+    }
+
+    async fn queue_of(policy: QueuePolicy, commands: Vec<Command>) -> CommandQueue {
+        let (tx, rx) = mpsc::channel(100);
+        for command in commands {
+            tx.send(QueuedCommand::new(Source::Internal, command)).await.unwrap();
+        }
+        CommandQueue::new(rx, policy)
+    }
+
+    async fn drain(queue: &mut CommandQueue) -> Vec<Command> {
+        let mut commands = Vec::new();
+        while let Ok(Some(queued)) = tokio::time::timeout(std::time::Duration::from_millis(10), queue.recv()).await {
+            commands.push(queued.command);
+        }
+        commands
+    }
+
+    fn positions(commands: &[Command]) -> Vec<String> {
+        commands.iter().map(|c| match c {
+            Command::Movement(action) => action.position.to_string(),
+            other => format!("{other:?}"),
+        }).collect()
+    }
+
+    #[tokio::test]
+    async fn latest_wins_collapses_runs_of_movement() {
+        let mut queue = queue_of(QueuePolicy::LatestWins, vec![
+            movement(0.1), movement(0.2), movement(0.3),
+            Command::Home,
+            movement(0.4), movement(0.5),
+            Command::Halt,
+            movement(0.6),
+        ]).await;
+        let commands = drain(&mut queue).await;
+        assert_eq!(positions(&commands), ["0.3", "Home", "0.5", "Halt", "0.6"]);
+        assert_eq!(queue.stats().coalesced(), 3);
+        assert_eq!(queue.stats().delivered(), 5);
+    }
+
+    #[tokio::test]
+    async fn fifo_sends_everything() {
+        let mut queue = queue_of(QueuePolicy::Fifo, vec![movement(0.1), movement(0.2), Command::Halt, movement(0.3)]).await;
+        let commands = drain(&mut queue).await;
+        assert_eq!(positions(&commands), ["0.1", "0.2", "Halt", "0.3"]);
+        assert_eq!(queue.stats().coalesced(), 0);
+    }
+
+    #[tokio::test]
+    async fn halt_skips_and_cancels_the_backlog() {
+        let mut queue = queue_of(QueuePolicy::LatestWins, vec![movement(0.1), Command::Home, movement(0.2), movement(0.3), Command::Halt, movement(0.4)]).await;
+        let halt = queue.recv_halt().await.unwrap();
+        assert!(matches!(halt.command, Command::Halt));
+        // nothing from before the halt runs after it
+        let commands = drain(&mut queue).await;
+        assert_eq!(positions(&commands), ["0.4"]);
+    }
+
+    #[tokio::test]
+    async fn halt_then_recv_keeps_what_came_after() {
+        let (tx, rx) = mpsc::channel(100);
+        let mut queue = CommandQueue::new(rx, QueuePolicy::Fifo);
+        for command in [movement(0.1), movement(0.2), Command::Halt] {
+            tx.send(QueuedCommand::new(Source::Internal, command)).await.unwrap();
+        }
+        assert!(matches!(queue.recv_halt().await.unwrap().command, Command::Halt));
+        for command in [Command::Home, movement(0.3)] {
+            tx.send(QueuedCommand::new(Source::Internal, command)).await.unwrap();
+        }
+        let commands = drain(&mut queue).await;
+        assert_eq!(positions(&commands), ["Home", "0.3"]);
+    }
+}
@@ -0,0 +1,240 @@
+//! Keeps a session's movement as a `.funscript`, so a good one can be played back later.
+//!
+//! Movements are taken either as the input decoded them or as they left the pipeline, see
+//! [`RecordPoint`]. The file is written when recording is turned off or the session stops.
+
+use std::fs::OpenOptions;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use crate::config::{choice, RecordingConfig};
+use crate::command::{Command, LinearModifier, QueuedCommand};
+
+#[derive(Debug, Error)]
+pub enum RecorderError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Serde(#[from] serde_json::Error),
+    #[error("no data directory could be found for this user")]
+    NoDataDir,
+}
+
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Default, Serialize, Deserialize)]
+pub enum RecordPoint {
+    /// what the other end sent
+    #[default]
+    BeforeFilters,
+    /// what the printer was told, after the profile's pipeline
+    AfterFilters,
+}
+
+choice!(RecordPoint, "record point", [BeforeFilters, AfterFilters]);
+
+/// What a funscript player expects: a list of points to be at, positions 0 to 100.
+#[derive(Debug, Serialize)]
+struct Funscript<'a> {
+    version: &'static str,
+    inverted: bool,
+    range: u8,
+    actions: &'a [FunscriptAction],
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+struct FunscriptAction {
+    /// ms from the start of the recording
+    at: u64,
+    pos: u8,
+}
+
+#[derive(Debug)]
+struct Recording {
+    point: RecordPoint,
+    started: Instant,
+    started_at: chrono::DateTime<chrono::Local>,
+    actions: Vec<FunscriptAction>,
+}
+
+impl Recording {
+    fn new(point: RecordPoint) -> Self {
+        Recording { point, started: Instant::now(), started_at: chrono::Local::now(), actions: Vec::new() }
+    }
+
+    /// A move taking `ms` lands at the end of it, which is where a funscript puts its point.
+    fn push(&mut self, queued: &QueuedCommand) {
+        let Command::Movement(action) = &queued.command else { return };
+        // from before recording started
+        let Some(since) = queued.received.checked_duration_since(self.started) else { return };
+        let duration = match action.modifier {
+            Some(LinearModifier::TIME(ms)) => ms as u64,
+            _ => 0,
+        };
+        let at = since.as_millis() as u64 + duration;
+        // a newer move cut these off before they got there
+        while self.actions.last().is_some_and(|last| last.at >= at) {
+            self.actions.pop();
+        }
+        self.actions.push(FunscriptAction { at, pos: (action.position.clamp(0.0, 1.0) * 100.0).round() as u8 });
+    }
+
+    /// `$XDG_DATA_HOME/inti-e3m/recordings/<start time>.funscript` (or the platform equivalent)
+    fn save(&self) -> Result<PathBuf, RecorderError> {
+        let mut dir = dirs::data_dir().ok_or(RecorderError::NoDataDir)?;
+        dir.push("inti-e3m");
+        dir.push("recordings");
+        self.save_in(&dir)
+    }
+
+    /// Never over an older one, a second recording that started in the same second gets `_2` etc.
+    fn save_in(&self, dir: &Path) -> Result<PathBuf, RecorderError> {
+        std::fs::create_dir_all(dir)?;
+        let json = serde_json::to_string(&self.funscript())?;
+        let stem = self.started_at.format("%Y-%m-%d_%H-%M-%S").to_string();
+        for n in 1.. {
+            let path = match n {
+                1 => dir.join(format!("{stem}.funscript")),
+                n => dir.join(format!("{stem}_{n}.funscript")),
+            };
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(json.as_bytes())?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        unreachable!()
+    }
+
+    fn funscript(&self) -> Funscript<'_> {
+        Funscript { version: "1.0", inverted: false, range: 100, actions: &self.actions }
+    }
+}
+
+/// Shared between the pipeline, which feeds it, and whoever turns it on and off.
+#[derive(Debug, Clone, Default)]
+pub struct Recorder {
+    recording: Arc<Mutex<Option<Recording>>>,
+}
+
+impl Recorder {
+    /// Start or stop to match `config`. Changing the point saves what's there and starts over.
+    pub fn update(&self, config: &RecordingConfig) {
+        let finished = {
+            let mut recording = self.recording.lock().unwrap();
+            if recording.as_ref().map(|r| r.point) == config.enabled.then_some(config.point) { return; }
+            let finished = recording.take();
+            if config.enabled {
+                log::info!("Recording movement {}", config.point);
+                *recording = Some(Recording::new(config.point));
+            }
+            finished
+        };
+        // saved with the lock let go, so the pipeline isn't held up by the disk
+        if let Some(finished) = finished {
+            finish(finished);
+        }
+    }
+
+    /// Keep `queued` if it's a movement and we're recording at `point`.
+    pub fn record(&self, point: RecordPoint, queued: &QueuedCommand) {
+        if let Some(recording) = self.recording.lock().unwrap().as_mut().filter(|r| r.point == point) {
+            recording.push(queued);
+        }
+    }
+
+    /// Save and stop, e.g. when the session ends.
+    pub fn stop(&self) {
+        let finished = self.recording.lock().unwrap().take();
+        if let Some(finished) = finished {
+            finish(finished);
+        }
+    }
+
+    /// How many points are in the recording, `None` if not recording.
+    pub fn recorded(&self) -> Option<usize> {
+        self.recording.lock().unwrap().as_ref().map(|r| r.actions.len())
+    }
+}
+
+fn finish(recording: Recording) {
+    if recording.actions.is_empty() {
+        log::info!("Nothing was recorded");
+        return;
+    }
+    match recording.save() {
+        Ok(path) => log::info!("Saved {} recorded actions to {}", recording.actions.len(), path.display()),
+        Err(e) => log::error!("Could not save the recording: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::command::{Action, LinearAction, Source};
+    use super::*;
+
+    fn movement(recording: &Recording, after_ms: u64, position: f32, ms: Option<u32>) -> QueuedCommand {
+        let mut queued = QueuedCommand::new(Source::Intiface, Command::Movement(LinearAction { action: Action::MOVE, id: 0, position, modifier: ms.map(LinearModifier::TIME) }));
+        queued.received = recording.started + Duration::from_millis(after_ms);
+        queued
+    }
+
+    #[test]
+    fn at_only_goes_up() {
+        let mut recording = Recording::new(RecordPoint::BeforeFilters);
+        for (after, position, ms) in [(0, 0.0, Some(500)), (100, 0.5, Some(100)), (150, 1.0, Some(500)), (300, 0.2, None)] {
+            let queued = movement(&recording, after, position, ms);
+            recording.push(&queued);
+        }
+        // the 500 ms move to 1.0 was cut off by the one at 300
+        let at: Vec<u64> = recording.actions.iter().map(|a| a.at).collect();
+        assert_eq!(at, [200, 300]);
+        assert!(recording.actions.windows(2).all(|w| w[0].at < w[1].at));
+    }
+
+    #[test]
+    fn pos_is_0_to_100() {
+        let mut recording = Recording::new(RecordPoint::BeforeFilters);
+        for (after, position) in [(0, -0.5), (10, 0.333), (20, 1.5)] {
+            let queued = movement(&recording, after, position, None);
+            recording.push(&queued);
+        }
+        let pos: Vec<u8> = recording.actions.iter().map(|a| a.pos).collect();
+        assert_eq!(pos, [0, 33, 100]);
+    }
+
+    #[test]
+    fn funscript_json() {
+        let mut recording = Recording::new(RecordPoint::BeforeFilters);
+        let queued = movement(&recording, 10, 0.25, Some(90));
+        recording.push(&queued);
+        assert_eq!(serde_json::to_value(recording.funscript()).unwrap(), serde_json::json!({
+            "version": "1.0",
+            "inverted": false,
+            "range": 100,
+            "actions": [{ "at": 100, "pos": 25 }],
+        }));
+    }
+
+    #[test]
+    fn same_second_doesnt_overwrite() {
+        let dir = std::env::temp_dir().join(format!("inti-e3m-recorder-test-{}", std::process::id()));
+        let mut first = Recording::new(RecordPoint::BeforeFilters);
+        let queued = movement(&first, 0, 0.0, None);
+        first.push(&queued);
+        let mut second = Recording { started_at: first.started_at, ..Recording::new(RecordPoint::BeforeFilters) };
+        let queued = movement(&second, 0, 1.0, None);
+        second.push(&queued);
+        let paths = [first.save_in(&dir).unwrap(), second.save_in(&dir).unwrap()];
+        let saved = paths.each_ref().map(|path| std::fs::read_to_string(path).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_ne!(paths[0], paths[1]);
+        assert!(paths[1].to_string_lossy().ends_with("_2.funscript"));
+        assert!(saved[0].contains(r#""pos":0"#) && saved[1].contains(r#""pos":100"#));
+    }
+}
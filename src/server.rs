@@ -1,78 +1,196 @@
 // its only sort of a server... but wrapping this into one made sense.
 
-use color_eyre::eyre::private::kind::TraitKind;
-use ratatui::prelude::Span;
-use crate::config::{Config, ServiceProvider};
-use crate::tui::event::{AppEvent, ErrorKind, Event};
-use crate::usb::GCodeError;
-use crate::websocket::ClientError;
-use crate::Command;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use crate::backend::MotionBackend;
+use crate::config::{Config, RestartPolicy, ServiceProvider};
+use crate::input::InputSource;
+use crate::metrics::METRICS;
+use crate::pipeline::{Pipeline, Stage};
+use crate::queue::{CommandQueue, QueueStats};
+use crate::recorder::Recorder;
+use crate::telemetry::{error_chain, Diagnose, ErrorKind, ErrorReport, Status, Telemetry, TelemetrySender};
+use crate::command::QueuedCommand;
+use crate::usb::MarlinSerial;
+use crate::websocket::{Intiface, XToys};
 use thiserror::Error;
-use tokio::io;
-use tokio::sync::mpsc::{Receiver, Sender, UnboundedSender};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::watch;
 use tokio::task::{JoinError, JoinHandle};
 use tokio_util::sync::CancellationToken;
-use crate::tui::bar::Status;
+
+/// First delay before restarting a side, doubled each time it fails again.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(30);
+/// Running this long counts as healthy, so the backoff and failure count start over.
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+/// Give up after this many failures in a row.
+const MAX_CONSECUTIVE_FAILURES: u32 = 5;
 
 #[derive(Debug, Error)]
 pub enum ServerError {
-    #[error(transparent)]
-    Websocket(#[from] ClientError),
+    #[error("{}", .0.chain.join(": "))]
+    Input(ErrorReport),
+    #[error("{}", .0.chain.join(": "))]
+    Backend(ErrorReport),
     #[error(transparent)]
     Tokio(#[from] JoinError),
-    #[error(transparent)]
-    Gcode(#[from] GCodeError)
 }
 
-async fn start_with_configs(channel: (Sender<Command>, Receiver<Command>), token: CancellationToken, config: Config, app_tx: UnboundedSender<Event>) -> Result<(), ServerError> {
-    let (tx, rx) = channel;
-    let token = token.clone();
+/// Decides whether a side of the server gets brought back up, and waits out the backoff if so.
+struct Restarter {
+    name: &'static str,
+    policy: RestartPolicy,
+    backoff: Duration,
+    failures: u32,
+    started: Instant,
+}
 
-    let mut r = tokio::select! {
-        result = crate::usb::run_server(config.machine_config, rx, app_tx.clone() ,token.clone()) => result.map_err(ServerError::from),
-        result = crate::websocket::intiface(&config.websocket_config, tx.clone(), token.clone()),
-            if config.websocket_config.provider == ServiceProvider::INTI => result.map_err(ServerError::from),
-        result = crate::websocket::extoys(&config.websocket_config, tx.clone(), token.clone()),
-            if config.websocket_config.provider == ServiceProvider::EXTOY => result.map_err(ServerError::from)
-    };
-    if token.is_cancelled() {
-        log::info!("Server closed manually!");
+impl Restarter {
+    fn new(name: &'static str, policy: RestartPolicy) -> Self {
+        Restarter { name, policy, backoff: RESTART_BACKOFF, failures: 0, started: Instant::now() }
     }
-    if let Err(e) = &r {
-        token.cancel();
-        log::error!("{}", e);
 
-        let kind = match &e {
-            ServerError::Websocket(ClientError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => ErrorKind::Websocket("Invalid URI".to_string()),
-            ServerError::Websocket(we) =>  ErrorKind::Websocket("Error".to_string()),
-            //ServerError::Gcode(GCodeError::Io(e)) if e.kind() == io::ErrorKind::ConnectionAborted => ErrorKind::GCode("Not connected!".to_string()),
-            ServerError::Gcode(GCodeError::Io(e)) if e.kind() == io::ErrorKind::PermissionDenied => ErrorKind::GCode("No permission!".to_string()),
-            ServerError::Gcode(GCodeError::Io(e)) if e.kind() == io::ErrorKind::NotFound => ErrorKind::GCode("Not connected!".to_string()),
-            ServerError::Gcode(ge) => ErrorKind::GCode("Error".to_string()),
-            ServerError::Tokio(_) => ErrorKind::Websocket("Not connected!".to_string()) // unlikely and if we do its a bigger problem
+    /// `status` is called with the status to show while waiting to restart.
+    async fn should_restart<E: StdError>(&mut self, result: &Result<(), E>, token: &CancellationToken, status: impl Fn(Status)) -> bool {
+        if token.is_cancelled() { return false; }
+        if self.started.elapsed() > HEALTHY_AFTER {
+            self.backoff = RESTART_BACKOFF;
+            self.failures = 0;
+        }
+        let restart = match (result, self.policy) {
+            (_, RestartPolicy::Never) | (Ok(()), RestartPolicy::OnFailure) => false,
+            (Err(_), _) => {
+                self.failures += 1;
+                self.failures <= MAX_CONSECUTIVE_FAILURES
+            }
+            (Ok(()), RestartPolicy::Always) => true,
         };
-        app_tx.send(Event::App(AppEvent::ServerError(kind))).expect("app error channel went wrong, you're on your own.");
+        match result {
+            Err(e) if restart => log::warn!("{} failed, restarting in {:?}: {}", self.name, self.backoff, error_chain(e).join(": ")),
+            Err(e) => log::error!("{} failed: {}", self.name, error_chain(e).join(": ")),
+            Ok(()) if restart => log::info!("{} finished, restarting", self.name),
+            Ok(()) => log::info!("{} finished", self.name),
+        }
+        if !restart { return false; }
+
+        status(Status::Degraded(format!("Restarting in {}s", self.backoff.as_secs())));
+        tokio::select! {
+            _ = token.cancelled() => return false,
+            _ = tokio::time::sleep(self.backoff) => {}
+        }
+        if result.is_err() {
+            self.backoff = (self.backoff * 2).min(MAX_RESTART_BACKOFF);
+        }
+        self.started = Instant::now();
+        true
     }
+}
 
-    r
+/// The printer side. If this stops for good there's nothing left to do, so the whole server goes.
+async fn supervise_backend<B: MotionBackend>(mut backend: B, mut queue: CommandQueue, token: CancellationToken, telemetry: TelemetrySender) -> Result<(), ServerError> {
+    let mut restarter = Restarter::new(backend.name(), backend.restart_policy());
+    let result = loop {
+        let result = backend.run(&mut queue, telemetry.clone(), token.clone()).await;
+        let status = |s| { let _ = telemetry.send(Telemetry::BackendStatus(s)); };
+        if !restarter.should_restart(&result, &token, status).await {
+            break result.map_err(|e| ServerError::Backend(e.report()));
+        }
+        METRICS.backend_restarts.inc();
+    };
+    if let Err(ServerError::Backend(report)) = &result {
+        let _ = telemetry.send(Telemetry::Error(ErrorKind::Backend(report.clone())));
+    }
+    token.cancel();
+    result
 }
 
+/// The input side. Stopping this leaves the printer homed and usable from the keyboard/console.
+async fn supervise_input<I: InputSource>(mut input: I, tx: Sender<QueuedCommand>, token: CancellationToken, telemetry: TelemetrySender) -> Result<(), ServerError> {
+    let mut restarter = Restarter::new(input.name(), input.restart_policy());
+    let result = loop {
+        let result = input.run(tx.clone(), telemetry.clone(), token.clone()).await;
+        let status = |s| { let _ = telemetry.send(Telemetry::InputStatus(s)); };
+        if !restarter.should_restart(&result, &token, status).await {
+            break result.map_err(|e| ServerError::Input(e.report()));
+        }
+        METRICS.input_restarts.inc();
+    };
+    match &result {
+        Err(ServerError::Input(report)) => { let _ = telemetry.send(Telemetry::Error(ErrorKind::Input(report.clone()))); }
+        Ok(()) if !token.is_cancelled() => { let _ = telemetry.send(Telemetry::InputStatus(Status::NotRunning)); }
+        _ => {}
+    }
+    result
+}
+
+/// Runs both sides as their own tasks and waits for them.
+async fn run<I: InputSource, B: MotionBackend>(input: I, backend: B, tx: Sender<QueuedCommand>, queue: CommandQueue, token: CancellationToken, telemetry: TelemetrySender) -> Result<(), ServerError> {
+    let backend = tokio::spawn(supervise_backend(backend, queue, token.clone(), telemetry.clone()));
+    let input = tokio::spawn(supervise_input(input, tx, token.clone(), telemetry));
+
+    let (backend, input) = tokio::join!(backend, input);
+    if token.is_cancelled() {
+        log::info!("Server closed manually!");
+    }
+    // the printer side is what matters, report that first
+    backend??;
+    input??;
+    Ok(())
+}
+
+/// An input source feeding a motion backend through a pipeline, each side restarted on its own when it fails.
 #[derive(Debug)]
 pub struct Server {
-    pub(crate) token: CancellationToken,
-    pub(crate) tx: Sender<Command>,
-    pub(crate) handle: JoinHandle<Result<(), ServerError>>,
+    pub token: CancellationToken,
+    /// for commands that don't come from the input source, e.g. the keyboard. goes through the pipeline too.
+    pub tx: Sender<QueuedCommand>,
+    pub handle: JoinHandle<Result<(), ServerError>>,
+    pub queue_stats: Arc<QueueStats>,
+    /// swap the pipeline while running
+    pub stages: watch::Sender<Vec<Stage>>,
+    /// turned on and off while running, saves when the session ends
+    pub recorder: Recorder,
 }
 
 impl Server {
-    pub fn start(config: Config, app_tx: UnboundedSender<Event>) -> Self {
+    pub fn start<I: InputSource, B: MotionBackend>(input: I, stages: Vec<Stage>, backend: B, telemetry: TelemetrySender) -> Self {
         let token = CancellationToken::new();
-        let channel = tokio::sync::mpsc::channel::<Command>(100);
+        // input -> pipeline -> queue -> backend
+        let (tx, unfiltered) = tokio::sync::mpsc::channel::<QueuedCommand>(100);
+        let (filtered_tx, filtered) = tokio::sync::mpsc::channel::<QueuedCommand>(100);
+        let (stages_tx, stages_rx) = watch::channel(stages);
+        let pipeline = Pipeline::new(&stages_rx.borrow());
+        let recorder = Recorder::default();
+        tokio::spawn(pipeline.run(unfiltered, filtered_tx, stages_rx, recorder.clone(), token.clone()));
+        let queue = CommandQueue::new(filtered, backend.queue_policy());
         Self {
             token: token.clone(),
-            tx: channel.0.clone(),
-            handle: tokio::spawn(start_with_configs(channel, token, config, app_tx))
+            tx: tx.clone(),
+            queue_stats: queue.stats(),
+            stages: stages_tx,
+            recorder,
+            handle: tokio::spawn(run(input, backend, tx, queue, token, telemetry))
         }
     }
 
+    /// Intiface or XToys driving a serial printer, as set up in the active profile.
+    pub fn from_config(config: &Config, telemetry: TelemetrySender) -> Self {
+        let profile = config.profile();
+        log::info!("Starting server with profile \"{}\"", profile.name);
+        let backend = MarlinSerial { config: profile.machine_config.clone() };
+        let pipeline = profile.pipeline.clone();
+        let websocket_config = profile.websocket_config.clone();
+        let server = match websocket_config.provider {
+            ServiceProvider::INTI => Server::start(Intiface { config: websocket_config }, pipeline, backend, telemetry),
+            ServiceProvider::EXTOY => Server::start(XToys { config: websocket_config }, pipeline, backend, telemetry),
+        };
+        server.recorder.update(&config.recording);
+        server
+    }
+
+    pub fn is_running(&self) -> bool {
+        !self.handle.is_finished() && !self.tx.is_closed() && !self.token.is_cancelled()
+    }
 }
@@ -1,6 +1,6 @@
 use std::str::Utf8Error;
 use thiserror::Error;
-use crate::usb::{Action, LinearAction, LinearModifier};
+use crate::command::{Action, LinearAction, LinearModifier};
 
 #[derive(Error, Debug)]
 pub enum LinearActionError {
@@ -30,10 +30,10 @@ pub fn process_linear_token(bytes: &[u8]) -> Result<LinearAction, LinearActionEr
         .ok_or_else(|| LinearActionError::InvalidID(bytes[1] as char))?;
 
     let position = bytes.iter().position(|&c| { let c = c as char; c == 'I' || c == 'i' || c == 'S' || c == 's' });
-    let magnitude: u32;
+    let magnitude: &str;
     let modifier: Option<LinearModifier>;
     if let Some(i) = position { // we have a time modifier, only consume up to it.
-        magnitude = str::from_utf8(&bytes[2..i])?.parse()?; // get magnitude
+        magnitude = str::from_utf8(&bytes[2..i])?; // get magnitude
         let n: u32;
         n = str::from_utf8(&bytes[(i+1)..])?.parse()?; // get time/speed thing
 
@@ -44,13 +44,40 @@ pub fn process_linear_token(bytes: &[u8]) -> Result<LinearAction, LinearActionEr
         }
     } else {
         modifier = None;
-        magnitude = str::from_utf8(&bytes[2..])?.parse()?;
+        magnitude = str::from_utf8(&bytes[2..])?;
     };
+    // the digits are everything after the decimal point, so 05 is 0.05 and 5 is 0.5
+    let position = magnitude.parse::<u32>()? as f32 / 10f32.powi(magnitude.len() as i32);
 
     Ok(LinearAction {
         action,
         id,
-        magnitude,
+        position,
         modifier
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(token: &str) -> f32 {
+        process_linear_token(token.as_bytes()).unwrap().position
+    }
+
+    #[test]
+    fn digits_are_a_fraction() {
+        assert!((position("L05") - 0.5).abs() < 1e-6);
+        assert!((position("L099") - 0.99).abs() < 1e-6);
+        assert!((position("L9999") - 0.999).abs() < 1e-6);
+        assert!((position("L0050") - 0.05).abs() < 1e-6);
+    }
+
+    #[test]
+    fn id_and_interval() {
+        let action = process_linear_token(b"L9999I250").unwrap();
+        assert_eq!(action.id, 9);
+        assert!((action.position - 0.999).abs() < 1e-6);
+        assert!(matches!(action.modifier, Some(LinearModifier::TIME(250))));
+    }
+}
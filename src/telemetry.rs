@@ -0,0 +1,134 @@
+//! Everything the server reports while it runs. The TUI is just one thing listening to this.
+
+use std::error::Error as StdError;
+use std::time::Duration;
+use serde::Serialize;
+use tokio::sync::mpsc::UnboundedSender;
+use crate::firmware::{FirmwareCapabilities, Position};
+use crate::command::Source;
+
+pub type TelemetrySender = UnboundedSender<Telemetry>;
+
+#[derive(Debug, Clone, Serialize)]
+pub enum Telemetry {
+    /// input side came up, got a client, etc.
+    InputStatus(Status),
+    /// printer connection changed, e.g. unplugged and waiting for it to come back
+    BackendStatus(Status),
+    /// a line of G-code was written to the printer
+    GCode(SentGCode),
+    /// the firmware acknowledged line `id`, with anything it said before the `ok`
+    GCodeReply(u64, String),
+    /// M115 reply from a newly connected printer, `None` if it didn't give one
+    Firmware(Option<FirmwareCapabilities>),
+    /// X we last told the printer to go to, in mm
+    CommandedPosition(f32),
+    /// where the printer says it is
+    ReportedPosition(Position),
+    /// something happened to a movement on its way to the printer, with the X it was going to
+    Marker(MotionMarker, f32),
+    /// a movement was acknowledged, and how long each leg took
+    Latency(Latency),
+    /// a side stopped for good
+    Error(ErrorKind),
+}
+
+/// Where a service (input or backend) is in its lifecycle, reported by the service itself.
+#[derive(Debug, Clone, Serialize)]
+pub enum Status {
+    NotRunning,
+    Stopped(String),
+    /// setting up a listener, or opening the serial port
+    Binding,
+    /// waiting for a client to connect, on this address
+    Listening(String),
+    /// connecting out to this address (Intiface)
+    Connecting(String),
+    /// the other end is there (peer address or serial port), but not ready yet
+    Connected(String),
+    /// websocket handshake done, waiting for commands
+    Handshaken,
+    Homing,
+    Ready,
+    /// still running but not usable right now, e.g. waiting for a device to come back
+    Degraded(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SentGCode {
+    /// unique per line, used to match up the reply
+    pub id: u64,
+    pub line: String,
+    pub source: Source,
+    pub sent_at: chrono::DateTime<chrono::Local>,
+}
+
+/// Where the time went for one movement.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct Latency {
+    /// from the input receiving it to its G-code being written, i.e. the pipeline and queue
+    pub queued: Duration,
+    /// from writing the G-code to the printer's `ok`, i.e. the serial link and planner
+    pub printer: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum MotionMarker {
+    /// slowed down to the max feedrate
+    Limited,
+    /// never sent, e.g. while paused or unplugged
+    Dropped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum ErrorKind {
+    Input(ErrorReport),
+    Backend(ErrorReport)
+}
+
+impl ErrorKind {
+    pub fn report(&self) -> &ErrorReport {
+        match self {
+            ErrorKind::Input(report) | ErrorKind::Backend(report) => report,
+        }
+    }
+}
+
+/// Everything known about why a side of the server stopped, for the error details popup.
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorReport {
+    /// short and stable, e.g. `USB-PERM`, shown in the status bar
+    pub code: &'static str,
+    /// what the status bar used to say, e.g. "No permission!"
+    pub summary: String,
+    /// the error followed by each of its sources
+    pub chain: Vec<String>,
+    /// what to try next, if there's an obvious fix
+    pub hint: Option<&'static str>,
+    pub at: chrono::DateTime<chrono::Local>,
+}
+
+/// Errors that can explain themselves to the user.
+pub trait Diagnose: StdError {
+    /// a short code, a status bar summary and maybe a hint at a fix
+    fn diagnose(&self) -> (&'static str, &'static str, Option<&'static str>);
+
+    fn report(&self) -> ErrorReport where Self: Sized {
+        let (code, summary, hint) = self.diagnose();
+        ErrorReport { code, summary: summary.to_string(), chain: error_chain(self), hint, at: chrono::Local::now() }
+    }
+}
+
+/// The error followed by each of its causes, transparent errors only show their source once.
+pub fn error_chain(error: &dyn StdError) -> Vec<String> {
+    let mut chain = vec![error.to_string()];
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let cause_str = cause.to_string();
+        if chain.last() != Some(&cause_str) {
+            chain.push(cause_str);
+        }
+        source = cause.source();
+    }
+    chain
+}
@@ -1,6 +1,15 @@
 pub(crate) mod app;
 pub(crate) mod bar;
 pub(crate) mod config_option;
+pub(crate) mod console;
 pub(crate) mod event;
+pub(crate) mod history;
+#[cfg(feature = "http")]
+pub(crate) mod http;
+pub(crate) mod latency;
 pub(crate) mod popup;
+pub(crate) mod position;
+pub(crate) mod stroke_chart;
 pub(crate) mod ui;
+#[cfg(feature = "http")]
+pub(crate) mod web;
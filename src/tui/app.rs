@@ -1,9 +1,20 @@
-use crate::config::{Config, ServiceProvider};
-use crate::server::Server;
-use crate::tui::config_option::{ConfigOptType, ConfigOption};
-use crate::tui::event::{AppEvent, ErrorKind, Event, EventHandler};
-use crate::tui::popup::{DataType, PopupState};
-use crate::Command;
+use inti_e3m::config::{Config, DisconnectPolicy, FlowControl, MachineConfig, RestartPolicy, ServiceProvider};
+use inti_e3m::firmware::{FirmwareCapabilities, Position};
+use inti_e3m::pipeline::{parse_stages, stages_to_string, Stage};
+use inti_e3m::metrics::MetricsEndpoint;
+use inti_e3m::queue::{QueuePolicy, QueueStats};
+use serde::Serialize;
+use inti_e3m::recorder::RecordPoint;
+use inti_e3m::server::Server;
+use crate::tui::config_option::{profile_options, choice_options, serial_port_options, usb_id_options, baud_rate_options, on_off_options, ConfigOptType, ConfigOption};
+use crate::tui::event::{AppEvent, Event, EventHandler};
+#[cfg(feature = "http")]
+use crate::tui::http::HttpServer;
+#[cfg(unix)]
+use inti_e3m::control::ControlSocket;
+use inti_e3m::telemetry::{ErrorKind, Telemetry};
+use crate::tui::popup::{DataType, PopupState, SelectOption, SelectPopupState};
+use inti_e3m::command::Command;
 use log::{info};
 use ratatui::widgets::TableState;
 use ratatui::{
@@ -11,7 +22,14 @@ use ratatui::{
     DefaultTerminal,
 };
 use crate::tui::bar::ServicesState;
-use crate::tui::bar::Status::{NotRunning, Okay, Stopped};
+use crate::tui::position::PositionState;
+use crate::tui::stroke_chart::StrokeHistory;
+use crate::tui::history::GCodeHistory;
+use crate::tui::latency::LatencyHistory;
+use crate::tui::console::ConsoleState;
+use inti_e3m::command::{QueuedCommand, Source};
+use inti_e3m::telemetry::Status;
+use inti_e3m::telemetry::Status::{NotRunning, Stopped};
 
 /// Application.
 #[derive(Debug)]
@@ -19,6 +37,9 @@ pub struct App {
     /// Is the application running?
     pub running: bool,
     pub popup_state: Option<PopupState>,
+    pub select_state: Option<SelectPopupState>,
+    /// showing the details of `services_state.last_error`
+    pub error_popup: bool,
     pub table_state: TableState,
     pub items: Vec<ConfigOption>,
     pub config: Config,
@@ -27,6 +48,21 @@ pub struct App {
     pub events: EventHandler,
     pub(crate) server: Option<Server>,
     pub services_state: ServicesState,
+    /// which pane is shown under the G-code history
+    pub tab: Tab,
+    pub console: ConsoleState,
+    #[cfg(feature = "http")]
+    pub http: HttpServer,
+    #[cfg(unix)]
+    pub control: ControlSocket,
+    pub metrics: MetricsEndpoint,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tab {
+    Log,
+    /// typing goes to the console while this is open
+    Console,
 }
 
 
@@ -36,45 +72,175 @@ impl App {
             server: None,
             running: true,
             popup_state: None,
+            select_state: None,
+            error_popup: false,
             items: vec![
-                ConfigOption::new(ConfigOptType::PopupInput,"Serial File",
-                                  config.machine_config.file.as_str(),
-                                  |c| c.machine_config.file.to_string(),
-                                  |c,s| { c.machine_config.file = s.to_string(); Ok(()) }
-                ),
-                ConfigOption::new(ConfigOptType::PopupInput,"Movement distance",
-                                  format!("{} mm", config.machine_config.max_movement).as_str(),
-                                  |c| format!("{} mm", c.machine_config.max_movement),
-                                  |c,s| { c.machine_config.max_movement = s.parse()?; Ok(()) }
-                ),
-                ConfigOption::new(ConfigOptType::PopupInput,"Max throw",
-                                  format!("{} mm", config.machine_config.throw).as_str(),
-                                  |c| format!("{} mm", c.machine_config.throw),
-                                  |c,s| { c.machine_config.throw = s.parse()?; Ok(())}
-                ),
-                ConfigOption::new(ConfigOptType::PopupInput,"Max acceleration",
-                                  format!("{} mm/s", config.machine_config.max_acceleration).as_str(),
-                                  |c| format!("{} mm/s", c.machine_config.max_acceleration),
-                                  |c,s| { c.machine_config.max_acceleration = s.parse()?; Ok(()) }
-                ),
-                ConfigOption::new(ConfigOptType::PopupInput,"Websocket URI",
-                                  config.websocket_config.ws.as_str(),
-                                  |c| format!("{} mm/s", c.websocket_config.ws),
-                                  |c,s| { c.websocket_config.ws = s.to_string(); Ok(()) }
-                ),
-                ConfigOption::new(ConfigOptType::Switch,"Service Provider",
-                                  config.websocket_config.provider.to_string().as_str(),
-                                  |c| c.websocket_config.provider.to_string(),
+                ConfigOption::new(ConfigOptType::PopupSelect(profile_options),"Profile",
+                                  config.profile().name.as_str(),
+                                  |c| c.profile().name.clone(),
+                                  |c,s| { c.select_profile(s)?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(1, 30)),"Profile name",
+                                  config.profile().name.as_str(),
+                                  |c| c.profile().name.clone(),
+                                  |c,s| { c.rename_profile(s)?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(serial_port_options),"Serial File",
+                                  config.profile().machine_config.file.as_str(),
+                                  |c| c.profile().machine_config.file.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.file = s.to_string(); Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(usb_id_options),"Match USB id",
+                                  usb_id_repr(&config).as_str(),
+                                  usb_id_repr,
                                   |c,s| {
-                                      c.websocket_config.provider = if c.websocket_config.provider == ServiceProvider::INTI {
-                                          ServiceProvider::EXTOY
-                                      } else {
-                                          ServiceProvider::INTI
-                                      };
+                                      c.profile_mut().machine_config.usb_id = if s == "Off" { None } else { Some(s.parse()?) };
                                       Ok(())
                                   }
-                )
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(baud_rate_options),"Baud rate",
+                                  config.profile().machine_config.baud_rate.to_string().as_str(),
+                                  |c| c.profile().machine_config.baud_rate.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.baud_rate = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<FlowControl>),"Flow control",
+                                  config.profile().machine_config.flow_control.to_string().as_str(),
+                                  |c| c.profile().machine_config.flow_control.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.flow_control = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(on_off_options),"Reset on open",
+                                  on_off(config.profile().machine_config.reset_on_open),
+                                  |c| on_off(c.profile().machine_config.reset_on_open).to_string(),
+                                  |c,s| { c.profile_mut().machine_config.reset_on_open = s == "On"; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::UnsignedInteger(60, 60000)),"Max feedrate",
+                                  format!("{} mm/min", config.profile().machine_config.max_feedrate).as_str(),
+                                  |c| format!("{} mm/min", c.profile().machine_config.max_feedrate),
+                                  |c,s| { c.profile_mut().machine_config.max_feedrate = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<DisconnectPolicy>),"While disconnected",
+                                  config.profile().machine_config.disconnect_policy.to_string().as_str(),
+                                  |c| c.profile().machine_config.disconnect_policy.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.disconnect_policy = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<QueuePolicy>),"Motion queue",
+                                  config.profile().machine_config.queue_policy.to_string().as_str(),
+                                  |c| c.profile().machine_config.queue_policy.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.queue_policy = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<RestartPolicy>),"Restart printer",
+                                  config.profile().machine_config.restart.to_string().as_str(),
+                                  |c| c.profile().machine_config.restart.to_string(),
+                                  |c,s| { c.profile_mut().machine_config.restart = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::UnsignedInteger(1, 1000)),"Movement distance",
+                                  format!("{} mm", config.profile().machine_config.max_movement).as_str(),
+                                  |c| format!("{} mm", c.profile().machine_config.max_movement),
+                                  |c,s| {
+                                      let machine = &mut c.profile_mut().machine_config;
+                                      let max_movement = s.parse()?;
+                                      MachineConfig { max_movement, ..machine.clone() }.validate()?;
+                                      machine.max_movement = max_movement;
+                                      Ok(())
+                                  }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::UnsignedInteger(1, 1000)),"Max throw",
+                                  format!("{} mm", config.profile().machine_config.throw).as_str(),
+                                  |c| format!("{} mm", c.profile().machine_config.throw),
+                                  |c,s| {
+                                      let machine = &mut c.profile_mut().machine_config;
+                                      let throw = s.parse()?;
+                                      MachineConfig { throw, ..machine.clone() }.validate()?;
+                                      machine.throw = throw;
+                                      Ok(())
+                                  }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::UnsignedInteger(1, 1000000)),"Max acceleration",
+                                  format!("{} mm/s", config.profile().machine_config.max_acceleration).as_str(),
+                                  |c| format!("{} mm/s", c.profile().machine_config.max_acceleration),
+                                  |c,s| { c.profile_mut().machine_config.max_acceleration = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(5, 64)),"Websocket URI",
+                                  config.profile().websocket_config.ws.as_str(),
+                                  |c| c.profile().websocket_config.ws.clone(),
+                                  |c,s| { c.profile_mut().websocket_config.ws = s.to_string(); Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<ServiceProvider>),"Service Provider",
+                                  config.profile().websocket_config.provider.to_string().as_str(),
+                                  |c| c.profile().websocket_config.provider.to_string(),
+                                  |c,s| {
+                                      let provider: ServiceProvider = s.parse()?;
+                                      let profile = c.profile_mut();
+                                      // swap the pipeline too, unless it's been changed by hand
+                                      if profile.pipeline == profile.websocket_config.provider.default_pipeline() {
+                                          profile.pipeline = provider.default_pipeline();
+                                      }
+                                      profile.websocket_config.provider = provider;
+                                      Ok(())
+                                  }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(1, 256)),"Pipeline",
+                                  stages_to_string(&config.profile().pipeline).as_str(),
+                                  |c| stages_to_string(&c.profile().pipeline),
+                                  |c,s| { c.profile_mut().pipeline = parse_stages(s)?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<RestartPolicy>),"Restart websocket",
+                                  config.profile().websocket_config.restart.to_string().as_str(),
+                                  |c| c.profile().websocket_config.restart.to_string(),
+                                  |c,s| { c.profile_mut().websocket_config.restart = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(on_off_options),"Recording",
+                                  on_off(config.recording.enabled),
+                                  |c| on_off(c.recording.enabled).to_string(),
+                                  |c,s| { c.recording.enabled = s == "On"; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(choice_options::<RecordPoint>),"Record at",
+                                  config.recording.point.to_string().as_str(),
+                                  |c| c.recording.point.to_string(),
+                                  |c,s| { c.recording.point = s.parse()?; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupSelect(on_off_options),"Metrics",
+                                  on_off(config.metrics.enabled),
+                                  |c| on_off(c.metrics.enabled).to_string(),
+                                  |c,s| { c.metrics.enabled = s == "On"; Ok(()) }
+                ),
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(1, 64)),"Metrics bind",
+                                  config.metrics.bind.as_str(),
+                                  |c| c.metrics.bind.clone(),
+                                  |c,s| { c.metrics.bind = s.parse::<std::net::SocketAddr>()?.to_string(); Ok(()) }
+                ),
+                #[cfg(unix)]
+                ConfigOption::new(ConfigOptType::PopupSelect(on_off_options),"Control socket",
+                                  on_off(config.control_socket),
+                                  |c| on_off(c.control_socket).to_string(),
+                                  |c,s| { c.control_socket = s == "On"; Ok(()) }
+                ),
+                #[cfg(feature = "http")]
+                ConfigOption::new(ConfigOptType::PopupSelect(on_off_options),"HTTP API",
+                                  on_off(config.http.enabled),
+                                  |c| on_off(c.http.enabled).to_string(),
+                                  |c,s| { c.http.enabled = s == "On"; Ok(()) }
+                ),
+                #[cfg(feature = "http")]
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(1, 64)),"HTTP bind",
+                                  config.http.bind.as_str(),
+                                  |c| c.http.bind.clone(),
+                                  |c,s| { c.http.bind = s.parse::<std::net::SocketAddr>()?.to_string(); Ok(()) }
+                ),
+                #[cfg(feature = "http")]
+                ConfigOption::new(ConfigOptType::PopupInput(DataType::String(1, 64)),"Web UI socket",
+                                  config.http.websocket_bind.as_str(),
+                                  |c| c.http.websocket_bind.clone(),
+                                  |c,s| { c.http.websocket_bind = s.parse::<std::net::SocketAddr>()?.to_string(); Ok(()) }
+                ),
             ],
+            tab: Tab::Log,
+            console: ConsoleState::default(),
+            #[cfg(feature = "http")]
+            http: HttpServer::default(),
+            #[cfg(unix)]
+            control: ControlSocket::default(),
+            metrics: MetricsEndpoint::default(),
             table_state: TableState::default().with_selected(0).with_selected_column(1),
             events: EventHandler::new(),
             config,
@@ -82,22 +248,57 @@ impl App {
                 websocket_status: NotRunning,
                 usb_status: NotRunning,
                 latest_gcode: "".to_string(),
+                firmware: None,
+                position: PositionState::default(),
+                history: StrokeHistory::default(),
+                gcode_history: GCodeHistory::default(),
+                last_error: None,
+                latency: LatencyHistory::default(),
+                recorded: None,
             },
         }
     }
 }
 
 
+/// What the app is up to, for the HTTP API.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub server_running: bool,
+    pub websocket_status: Status,
+    pub usb_status: Status,
+    pub latest_gcode: String,
+    pub firmware: Option<FirmwareCapabilities>,
+    /// X we last told the printer to go to, in mm
+    pub commanded_x: Option<f32>,
+    pub reported: Option<Position>,
+    /// code and the whole error chain of the last thing that stopped
+    pub last_error: Option<(&'static str, String)>,
+    /// queue counters for the running server
+    pub delivered: u64,
+    pub coalesced: u64,
+    pub config: Config,
+}
+
+fn on_off(value: bool) -> &'static str {
+    if value { "On" } else { "Off" }
+}
+
+fn usb_id_repr(config: &Config) -> String {
+    config.profile().machine_config.usb_id.as_ref().map_or("Off".to_string(), |id| id.to_string())
+}
+
 impl App {
     pub(crate) fn is_server_running(&self) -> bool {
-        if let Some(server) = &self.server {
-            !server.handle.is_finished() && !server.tx.is_closed() && !server.token.is_cancelled()
-        } else {
-            false
-        }
+        self.server.as_ref().is_some_and(Server::is_running)
     }
     /// Run the application's main loop.
     pub async fn run(mut self, mut terminal: DefaultTerminal) -> color_eyre::Result<()> {
+        #[cfg(feature = "http")]
+        self.http.update(&self.config.http, &self.events);
+        #[cfg(unix)]
+        self.control.update(self.config.control_socket, self.events.control_sender(), &self.events.telemetry_broadcast());
+        self.metrics.update(&self.config.metrics);
         while self.running {
             terminal.draw(|frame| self.draw(frame));
             match self.events.next().await? {
@@ -110,42 +311,128 @@ impl App {
                     _ => {}
                 },
                 Event::App(app_event) => match app_event {
-                    AppEvent::Server => {
-                        if self.is_server_running() {
-                            if let Some(server) = &self.server {
-                                server.token.cancel();
-                                self.server = None;
-                                self.services_state.websocket_status = NotRunning;
-                                self.services_state.usb_status = NotRunning;
-                            }
-                        } else  {
-                            self.services_state.websocket_status = Okay;
-                            self.services_state.usb_status = Okay;
-                            self.server = Some(Server::start(self.config.clone(), self.events.sender.clone()));
-                        }
-                    }
+                    AppEvent::Server if self.is_server_running() => self.stop_server(),
+                    AppEvent::Server | AppEvent::StartServer => self.start_server(),
+                    AppEvent::StopServer => self.stop_server(),
+                    AppEvent::SetStage(stage) => self.set_stage(stage),
+                    AppEvent::Snapshot(reply) => { let _ = reply.send(self.snapshot()); }
+                    AppEvent::GetConfig(reply) => { let _ = reply.send(self.config.clone()); }
+                    AppEvent::SetConfig(config) => self.set_config(*config),
                     AppEvent::Quit => self.quit(),
-                    AppEvent::Command(command) if let Some(s) = &self.server => {
+                    AppEvent::Command(source, command) if let Some(s) = &self.server => {
                         if !s.tx.is_closed() {
-                            if let Err(e) = s.tx.send(command).await {
+                            if let Err(e) = s.tx.send(QueuedCommand::new(source, command)).await {
                                 log::error!("Failure sending command to marlin. Error: \n {}", e)
                             }
                         }
                     }
-                    AppEvent::GCode(gcode) => self.services_state.latest_gcode = gcode,
-                    AppEvent::Command(_) => {},
-                    AppEvent::ServerError(e) => {
-                        match e {
-                            ErrorKind::Websocket(e) => self.services_state.websocket_status = Stopped(e),
-                            ErrorKind::GCode(e) => self.services_state.usb_status = Stopped(e),
+                    AppEvent::Telemetry(telemetry) => self.handle_telemetry(telemetry),
+                    AppEvent::Command(..) => {},
+                    AppEvent::Console(line) if let Some(s) = &self.server => {
+                        if let Err(e) = s.tx.send(QueuedCommand::new(Source::Console, Command::Raw(line))).await {
+                            log::error!("Failure sending console line to marlin. Error: \n {}", e)
                         }
-                    },
+                    }
+                    AppEvent::Console(_) => {},
                 },
             }
         }
         Ok(())
     }
 
+    fn start_server(&mut self) {
+        if self.is_server_running() { return; }
+        // the services report their own status as they come up
+        self.server = Some(Server::from_config(&self.config, self.events.telemetry_sender()));
+    }
+
+    fn stop_server(&mut self) {
+        if let Some(server) = self.server.take() {
+            server.token.cancel();
+            self.services_state.websocket_status = NotRunning;
+            self.services_state.usb_status = NotRunning;
+        }
+    }
+
+    fn set_stage(&mut self, stage: Stage) {
+        let pipeline = &mut self.config.profile_mut().pipeline;
+        match pipeline.iter_mut().find(|s| std::mem::discriminant(*s) == std::mem::discriminant(&stage)) {
+            Some(existing) => *existing = stage,
+            None => pipeline.push(stage),
+        }
+        if let Some(server) = &self.server {
+            server.stages.send_replace(pipeline.clone());
+        }
+        self.config_changed();
+    }
+
+    /// Swap in a whole new config. The pipeline applies straight away, the rest on the next start.
+    fn set_config(&mut self, mut config: Config) {
+        config.sanitize();
+        if let Err(e) = config.validate() {
+            log::error!("New config rejected: {}", e);
+            return;
+        }
+        self.config = config;
+        if let Some(server) = &self.server {
+            server.stages.send_replace(self.config.profile().pipeline.clone());
+        }
+        self.config_changed();
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let state = &self.services_state;
+        let queue = self.server.as_ref().map(|s| s.queue_stats.as_ref());
+        Snapshot {
+            server_running: self.is_server_running(),
+            websocket_status: state.websocket_status.clone(),
+            usb_status: state.usb_status.clone(),
+            latest_gcode: state.latest_gcode.clone(),
+            firmware: state.firmware.clone(),
+            commanded_x: state.position.commanded,
+            reported: state.position.reported,
+            last_error: state.last_error.as_ref().map(|e| (e.report().code, e.report().chain.join(": "))),
+            delivered: queue.map_or(0, QueueStats::delivered),
+            coalesced: queue.map_or(0, QueueStats::coalesced),
+            config: self.config.clone(),
+        }
+    }
+
+    fn handle_telemetry(&mut self, telemetry: Telemetry) {
+        match telemetry {
+            Telemetry::GCode(gcode) => {
+                self.services_state.latest_gcode = gcode.line.clone();
+                self.console.push(&gcode);
+                self.services_state.gcode_history.push(gcode);
+            }
+            Telemetry::GCodeReply(id, reply) => {
+                self.console.reply(id, &reply);
+                self.services_state.gcode_history.reply(id, reply);
+            }
+            Telemetry::BackendStatus(status) => self.services_state.usb_status = status,
+            Telemetry::InputStatus(status) => self.services_state.websocket_status = status,
+            Telemetry::Firmware(firmware) => self.services_state.firmware = firmware,
+            Telemetry::CommandedPosition(x) => {
+                self.services_state.position.commanded = Some(x);
+                self.services_state.history.push_commanded(x);
+            }
+            Telemetry::ReportedPosition(position) => {
+                self.services_state.position.reported = Some(position);
+                self.services_state.history.push_reported(position.x);
+            }
+            Telemetry::Marker(marker, x) => self.services_state.history.push_marker(marker, x),
+            Telemetry::Latency(latency) => self.services_state.latency.push(latency),
+            Telemetry::Error(e) => {
+                let summary = e.report().summary.clone();
+                match e {
+                    ErrorKind::Input(_) => self.services_state.websocket_status = Stopped(summary),
+                    ErrorKind::Backend(_) => self.services_state.usb_status = Stopped(summary),
+                }
+                self.services_state.last_error = Some(e);
+            }
+        }
+    }
+
     pub fn next_row(&mut self) {
         let i = match self.table_state.selected() {
             Some(i) => {
@@ -173,32 +460,114 @@ impl App {
     /// Handles the key events and updates the state of [`App`].
     pub fn handle_key_events(&mut self, key_event: KeyEvent) -> color_eyre::Result<()> {
         match key_event.code {
+            // error details, closes on anything but only Esc/e are advertised
+            _ if self.error_popup => self.error_popup = false,
+            // list popup
+            KeyCode::Up | KeyCode::Char('w') if let Some(select) = self.select_state.as_mut() => select.previous(),
+            KeyCode::Down | KeyCode::Char('s') if let Some(select) = self.select_state.as_mut() => select.next(),
+            KeyCode::Esc if self.select_state.is_some() => self.select_state = None,
+            KeyCode::Enter if let Some(select) = self.select_state.take() => {
+                let Some(i) = self.table_state.selected() else { return Ok(()) };
+                match select.selected() {
+                    Some(SelectOption { value: Some(value), .. }) => {
+                        if let Err(e) = self.items[i].handle(&mut self.config, value) {
+                            log::error!("Could not set {}: {}", self.items[i].label, e);
+                        }
+                        self.config_changed();
+                    }
+                    Some(SelectOption { value: None, .. }) => { // fall back to typing it
+                        let data = DataType::String(1, 256);
+                        self.popup_state = Some(PopupState {
+                            header: self.items[i].label.clone(),
+                            description: None,
+                            entered_text: data.filter(&self.items[i].string_repr),
+                            data,
+                            error: None,
+                        });
+                    }
+                    None => {}
+                }
+            }
+            KeyCode::Char(_) if self.select_state.is_some() => {}
             // popup logic / alternative controls :)
             KeyCode::Char(c) if let Some(popup) = self.popup_state.as_mut() => {
-                if popup.entered_text.len() < popup.data.get_max_chars() {
+                if popup.entered_text.len() < popup.data.get_max_chars() && popup.data.accepts(&popup.entered_text, c) {
                     popup.entered_text.push(c);
+                    popup.error = None;
                 }
             }
             KeyCode::Esc if self.popup_state.is_some() => {
                 self.popup_state = None;
             }
-            KeyCode::Enter if let Some(popup) = &self.popup_state => {
-                match self.table_state.selected() {
-                    Some(i) => self.items[i].handle(&mut self.config, popup.entered_text.as_str()).expect(""),
-                    None => {},
-                };
-                self.popup_state = None;
+            KeyCode::Enter if let Some(popup) = self.popup_state.as_mut() => {
+                let result = popup.data.validate(&popup.entered_text).and_then(|_| match self.table_state.selected() {
+                    Some(i) => self.items[i].handle(&mut self.config, popup.entered_text.as_str()).map_err(|e| e.to_string()),
+                    None => Ok(()),
+                });
+                match result {
+                    Ok(()) => {
+                        self.popup_state = None;
+                        self.config_changed();
+                    }
+                    Err(e) => popup.error = Some(e), // keep the popup open so it can be fixed
+                }
             },
             KeyCode::Backspace if let Some(popup) = self.popup_state.as_mut() => {
                 popup.entered_text.pop();
+                popup.error = None;
+            }
+            // console
+            KeyCode::Tab => self.tab = if self.tab == Tab::Log { Tab::Console } else { Tab::Log },
+            KeyCode::Esc if self.tab == Tab::Console => self.tab = Tab::Log,
+            KeyCode::Char(c) if self.tab == Tab::Console && key_event.modifiers != KeyModifiers::CONTROL => self.console.input.push(c),
+            KeyCode::Backspace if self.tab == Tab::Console => { self.console.input.pop(); }
+            KeyCode::Up if self.tab == Tab::Console => self.console.recall_previous(),
+            KeyCode::Down if self.tab == Tab::Console => self.console.recall_next(),
+            KeyCode::Enter if self.tab == Tab::Console => {
+                if let Some(line) = self.console.submit() {
+                    if self.is_server_running() {
+                        self.events.send(AppEvent::Console(line));
+                    } else {
+                        info!("Start the server to send G-code.");
+                    }
+                }
             }
             // normal controls
-            KeyCode::Home => self.events.send(AppEvent::Command(Command::Home)),
-            KeyCode::End | KeyCode::Char('h') => self.events.send(AppEvent::Command(Command::Halt)),
+            KeyCode::Home => self.events.send(AppEvent::Command(Source::Keyboard, Command::Home)),
+            KeyCode::End | KeyCode::Char('h') => self.events.send(AppEvent::Command(Source::Keyboard, Command::Halt)),
+            KeyCode::Char('p') => self.events.send(AppEvent::Command(Source::Keyboard, Command::Pause)),
+            KeyCode::Char('k') => self.events.send(AppEvent::Command(Source::Keyboard, Command::Park)),
+            KeyCode::Char('r') => self.events.send(AppEvent::Command(Source::Keyboard, Command::Resume)),
+            KeyCode::Char('e') if self.services_state.last_error.is_some() => self.error_popup = true,
+            KeyCode::Char(' ') => self.services_state.gcode_history.toggle_pause(),
+            KeyCode::Char('v') => self.services_state.gcode_history.cycle_filter(),
+            KeyCode::PageUp => self.services_state.gcode_history.scroll_up(10),
+            KeyCode::PageDown => self.services_state.gcode_history.scroll_down(10),
+            KeyCode::Char('f') => self.services_state.history.show_reported = !self.services_state.history.show_reported,
+            KeyCode::Char('o') => {
+                self.config.recording.enabled = !self.config.recording.enabled;
+                self.config_changed();
+            }
             KeyCode::Esc | KeyCode::Char('q') => self.events.send(AppEvent::Quit),
             KeyCode::Char('c' | 'C') if key_event.modifiers == KeyModifiers::CONTROL => {
                 self.events.send(AppEvent::Quit)
             },
+            // profiles
+            KeyCode::Char('n') if !self.is_server_running() => {
+                self.config.create_profile();
+                self.config_changed();
+            }
+            KeyCode::Char('c') if !self.is_server_running() => {
+                self.config.duplicate_profile();
+                self.config_changed();
+            }
+            KeyCode::Delete if !self.is_server_running() => {
+                if self.config.delete_profile() {
+                    self.config_changed();
+                } else {
+                    info!("Can't delete the only profile.");
+                }
+            }
             KeyCode::Char('w') => self.next_row(),
             KeyCode::Char('s') => self.previous_row(),
             KeyCode::Enter if let Some(n) = self.table_state.selected() => { // open popup or submit popup info and see
@@ -206,15 +575,15 @@ impl App {
                     self.events.send(AppEvent::Server);
                 } else if !self.is_server_running() {
                     let item: &mut ConfigOption = &mut self.items[n];
-                    if item.typ == ConfigOptType::Switch { // the whole switch thing is SUCH a hack... idrc at this point though
-                        item.handle(&mut self.config, "");
-                    } else {
-                        // TODO: popup based off option selecteed.
+                    if let ConfigOptType::PopupSelect(options) = &item.typ {
+                        self.select_state = Some(SelectPopupState::new(item.label.clone(), options(&self.config), &item.string_repr));
+                    } else if let ConfigOptType::PopupInput(data) = &item.typ {
                         self.popup_state = Some(PopupState {
                             header: item.label.clone(),
                             description: None,
-                            entered_text: item.string_repr.clone(),
-                            data: DataType::String(0, 30),
+                            entered_text: data.filter(&item.string_repr),
+                            data: data.clone(),
+                            error: None,
                         });
                     }
                 } else {
@@ -237,8 +606,30 @@ impl App {
         }
     }
 
+    /// Refresh every option from the (possibly switched) active profile and save it to disk.
+    pub fn config_changed(&mut self) {
+        for item in self.items.iter_mut() {
+            item.string_repr = (item.update_str)(&self.config);
+        }
+        if let Err(e) = self.config.save() {
+            log::error!("Could not save config: {}", e);
+        }
+        #[cfg(feature = "http")]
+        self.http.update(&self.config.http, &self.events);
+        #[cfg(unix)]
+        self.control.update(self.config.control_socket, self.events.control_sender(), &self.events.telemetry_broadcast());
+        self.metrics.update(&self.config.metrics);
+        if let Some(server) = &self.server {
+            server.recorder.update(&self.config.recording);
+        }
+    }
+
     /// Set running to false to quit the application.
     pub fn quit(&mut self) {
+        // the server's tasks don't get to finish up once we return
+        if let Some(server) = &self.server {
+            server.recorder.stop();
+        }
         self.running = false;
     }
 
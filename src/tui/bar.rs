@@ -6,54 +6,80 @@ use ratatui::prelude::Widget;
 use ratatui::style::{Color, Stylize};
 use ratatui::text::{Line, Span};
 use ratatui::widgets::{Block, StatefulWidget};
-use crate::usb::GCodeError;
-use crate::websocket::ClientError;
+use inti_e3m::usb::GCodeError;
+use inti_e3m::firmware::FirmwareCapabilities;
+use crate::tui::position::PositionState;
+use crate::tui::stroke_chart::StrokeHistory;
+use crate::tui::history::GCodeHistory;
+use crate::tui::latency::LatencyHistory;
+use inti_e3m::telemetry::{ErrorKind, Status};
+use inti_e3m::websocket::ClientError;
 
 pub struct Bar;
 
-#[derive(Debug, Clone)]
-pub enum Status {
-    NotRunning,
-    Stopped(String),
-    Okay
+fn status_span(status: &Status) -> Span<'_> {
+    match status {
+        Status::NotRunning => Span::from("Inactive"),
+        Status::Stopped(error) => Span::from(error.as_str()).fg(Color::Red),
+        Status::Binding => Span::from("Binding").fg(Color::Yellow),
+        Status::Listening(address) => Span::from(format!("Listening on {address}")).fg(Color::Yellow),
+        Status::Connecting(address) => Span::from(format!("Connecting to {address}")).fg(Color::Yellow),
+        Status::Connected(peer) => Span::from(format!("Connected {peer}")).fg(Color::Cyan),
+        Status::Handshaken => Span::from("Handshake done").fg(Color::Green),
+        Status::Homing => Span::from("Homing").fg(Color::Cyan),
+        Status::Ready => Span::from("Ready").fg(Color::Green),
+        Status::Degraded(reason) => Span::from(reason.as_str()).fg(Color::Magenta),
+    }
 }
 #[derive(Debug)]
 pub struct ServicesState {
     pub websocket_status: Status,
     pub usb_status: Status,
     pub latest_gcode: String,
+    pub firmware: Option<FirmwareCapabilities>,
+    pub position: PositionState,
+    pub history: StrokeHistory,
+    pub gcode_history: GCodeHistory,
+    /// the latest reason either side stopped, shown in full with `e`
+    pub last_error: Option<ErrorKind>,
+    pub latency: LatencyHistory,
+    /// points in the funscript being recorded, `None` when not recording
+    pub recorded: Option<usize>,
 }
 impl Bar {
     fn render_left(&self, area: Rect, buf: &mut Buffer, state: &mut ServicesState) {
         area.width;
         Line::from(vec![
             Span::from(" WebSocket "),
-            match &state.websocket_status {
-                Status::NotRunning => Span::from("Inactive"),
-                Status::Stopped(error) => Span::from(error),
-                Status::Okay => Span::from("Tick")
-            },
+            status_span(&state.websocket_status),
             Span::from(" USB "),
-            match &state.usb_status {
-                Status::NotRunning => Span::from("Inactive"),
-                Status::Stopped(error) => Span::from(error),
-                Status::Okay => Span::from("Tick")
-            }
+            status_span(&state.usb_status),
         ]).render(area, buf);
+        if let Some(error) = &state.last_error {
+            Line::from(vec![
+                Span::from(format!(" {} ", error.report().code)).fg(Color::Red).bold(),
+                Span::from("e details ").fg(Color::Gray),
+            ]).right_aligned().render(area, buf);
+        }
     }
     fn render_right(&self, area: Rect, buf: &mut Buffer, state: &mut ServicesState) {
+        let recording = match state.recorded {
+            Some(points) => Span::from(format!("● REC {points} ")).fg(Color::Red).bold(),
+            None => Span::from("o record ").fg(Color::DarkGray),
+        };
         let span = if state.latest_gcode.is_empty() {
             Span::from("No GCode Sent")
         } else {
             Span::from(format!("Last Command: {}", state.latest_gcode))
         };
-        Line::from(span).right_aligned().render(area, buf);
+        Line::from(vec![recording, span]).right_aligned().render(area, buf);
     }
     fn render_centre(&self, area: Rect, buf: &mut Buffer, state: &mut ServicesState) { // controls !
         let keys = [
-            ("w/↑", "Up"),
-            ("s/↓", "Down"),
+            ("w/s", "Select"),
             ("Enter", "Edit"),
+            ("n/c/Del", "Profile"),
+            ("P/K/R", "Pause/Park/Resume"),
             ("H/End", "Halt"),
             ("X/Esc", "Quit"),
         ];
@@ -80,12 +106,9 @@ impl StatefulWidget for Bar {
     type State = ServicesState;
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
-        let horizontal = Layout::horizontal([
-            Constraint::Min(0),
-            Constraint::Length(60),
-            Constraint::Min(0),
-        ]);
-        let [left_bar, centre, right_bar] = horizontal.areas(area);
+        // the keys get their own row, so the status never gets squeezed out on a narrow terminal
+        let [status, centre] = Layout::vertical([Constraint::Length(1), Constraint::Length(1)]).areas(area);
+        let [left_bar, right_bar] = Layout::horizontal([Constraint::Fill(1), Constraint::Fill(1)]).areas(status);
 
         Block::new().style((Color::Gray,Color::Gray)).render(area, buf);
         self.render_left(left_bar, buf, state);
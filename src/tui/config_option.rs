@@ -2,12 +2,16 @@ use core::error;
 use std::error::Error;
 use std::fmt::Debug;
 use ratatui::widgets::Row;
-use crate::config::Config;
+use inti_e3m::config::{Choice, Config, UsbMatch};
+use crate::tui::popup::{DataType, SelectOption};
+use tokio_serial::SerialPortType;
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ConfigOptType {
-    Switch,
-    PopupInput
+    /// opens a popup that only accepts the given type
+    PopupInput(DataType),
+    /// opens a list to pick from, built when the popup opens so it can reflect e.g. plugged in devices.
+    PopupSelect(fn(&Config) -> Vec<SelectOption>),
 }
 pub struct ConfigOption {
     pub typ: ConfigOptType,
@@ -52,4 +56,66 @@ impl<'a> From<&ConfigOption> for Row<'a> {
     fn from(value: &ConfigOption) -> Self {
         Row::new(vec![value.label.clone(), value.string_repr.clone()])
     }
-}
\ No newline at end of file
+}
+pub fn profile_options(config: &Config) -> Vec<SelectOption> {
+    config.profiles.iter()
+        .map(|p| SelectOption::new(p.name.clone(), p.name.clone()))
+        .collect()
+}
+
+/// Every value of a [`Choice`] setting, e.g. `choice_options::<FlowControl>`.
+pub fn choice_options<T: Choice>(_: &Config) -> Vec<SelectOption> {
+    T::ALL.iter()
+        .map(|c| SelectOption::new(c.to_string(), c.to_string()))
+        .collect()
+}
+
+/// Rates Marlin is commonly built with, anything else can be typed in.
+pub fn baud_rate_options(_: &Config) -> Vec<SelectOption> {
+    let mut options: Vec<SelectOption> = [250000, 115200, 230400, 500000, 57600]
+        .iter()
+        .map(|b| SelectOption::new(b.to_string(), b.to_string()))
+        .collect();
+    options.push(SelectOption::custom("Other..."));
+    options
+}
+
+pub fn on_off_options(_: &Config) -> Vec<SelectOption> {
+    vec![SelectOption::new("On", "On"), SelectOption::new("Off", "Off")]
+}
+
+/// Serial devices that are plugged in right now, plus a way to type a path by hand.
+pub fn serial_port_options(_: &Config) -> Vec<SelectOption> {
+    let ports = tokio_serial::available_ports().unwrap_or_else(|e| {
+        log::warn!("Could not list serial ports: {}", e);
+        Vec::new()
+    });
+    let mut options: Vec<SelectOption> = ports.into_iter().map(|port| {
+        let label = match &port.port_type {
+            SerialPortType::UsbPort(usb) => format!("{} [{:04x}:{:04x}] {}",
+                port.port_name, usb.vid, usb.pid, usb.product.as_deref().unwrap_or("")),
+            _ => port.port_name.clone(),
+        };
+        SelectOption::new(label, port.port_name)
+    }).collect();
+    options.push(SelectOption::custom("Other..."));
+    options
+}
+
+/// Plugged in USB serial devices by id, for `MachineConfig::usb_id`.
+pub fn usb_id_options(_: &Config) -> Vec<SelectOption> {
+    let ports = tokio_serial::available_ports().unwrap_or_else(|e| {
+        log::warn!("Could not list serial ports: {}", e);
+        Vec::new()
+    });
+    let mut options = vec![SelectOption::new("Off (use Serial File)", "Off")];
+    options.extend(ports.into_iter().filter_map(|port| match port.port_type {
+        SerialPortType::UsbPort(usb) => {
+            let id = UsbMatch { vid: usb.vid, pid: usb.pid, serial_number: usb.serial_number };
+            Some(SelectOption::new(format!("{} {} (now {})", id, usb.product.as_deref().unwrap_or(""), port.port_name), id.to_string()))
+        }
+        _ => None,
+    }));
+    options.push(SelectOption::custom("Other..."));
+    options
+}
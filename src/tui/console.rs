@@ -0,0 +1,109 @@
+use std::collections::VecDeque;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::prelude::Widget;
+use ratatui::style::{Color, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Paragraph, StatefulWidget};
+use crate::tui::history::HistoryEntry;
+use inti_e3m::telemetry::SentGCode;
+use inti_e3m::command::Source;
+
+/// How many typed lines are kept. Their own, so a stream of movement can't push them out.
+const MAX_ENTRIES: usize = 100;
+
+/// Raw G-code typed by hand, with the firmware's replies underneath.
+pub struct Console;
+
+#[derive(Debug, Default)]
+pub struct ConsoleState {
+    pub input: String,
+    /// previously sent lines, oldest first
+    sent: Vec<String>,
+    /// where we are in `sent` while pressing up/down
+    recall: Option<usize>,
+    /// what was sent from here and the replies
+    output: VecDeque<HistoryEntry>,
+}
+
+impl ConsoleState {
+    /// Take the typed line to send, remembering it for recall.
+    pub fn submit(&mut self) -> Option<String> {
+        // only the command word, `M117 Hello` shouldn't shout
+        let line = match self.input.trim().split_once(char::is_whitespace) {
+            Some((command, rest)) => format!("{} {}", command.to_uppercase(), rest.trim_start()),
+            None => self.input.trim().to_uppercase(),
+        };
+        self.input.clear();
+        self.recall = None;
+        if line.is_empty() { return None; }
+        if self.sent.last() != Some(&line) {
+            self.sent.push(line.clone());
+        }
+        Some(line)
+    }
+
+    /// Keep `sent` if it came from the console.
+    pub fn push(&mut self, sent: &SentGCode) {
+        if sent.source != Source::Console { return; }
+        self.output.push_back(HistoryEntry { sent: sent.clone(), reply: None });
+        if self.output.len() > MAX_ENTRIES {
+            self.output.pop_front();
+        }
+    }
+
+    pub fn reply(&mut self, id: u64, reply: &str) {
+        if let Some(entry) = self.output.iter_mut().rev().find(|e| e.sent.id == id) {
+            entry.reply = Some(reply.to_string());
+        }
+    }
+
+    pub fn recall_previous(&mut self) {
+        if self.sent.is_empty() { return; }
+        let i = self.recall.map_or(self.sent.len() - 1, |i| i.saturating_sub(1));
+        self.recall = Some(i);
+        self.input = self.sent[i].clone();
+    }
+
+    pub fn recall_next(&mut self) {
+        match self.recall {
+            Some(i) if i + 1 < self.sent.len() => {
+                self.recall = Some(i + 1);
+                self.input = self.sent[i + 1].clone();
+            }
+            _ => {
+                self.recall = None;
+                self.input.clear();
+            }
+        }
+    }
+}
+
+impl StatefulWidget for Console {
+    type State = ConsoleState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let [output, input] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+
+        let mut lines = Vec::new();
+        for entry in &state.output {
+            lines.push(Line::from(vec![
+                Span::from(entry.sent.sent_at.format("%H:%M:%S ").to_string()).fg(Color::DarkGray),
+                Span::from(format!("> {}", entry.sent.line)).fg(Color::Cyan),
+            ]));
+            match &entry.reply {
+                Some(reply) => lines.extend(reply.split(" | ").map(|l| Line::from(format!("  {l}")).fg(Color::Green))),
+                None => lines.push(Line::from("  ...").fg(Color::DarkGray)),
+            }
+        }
+        // keep the newest in view
+        let scroll = lines.len().saturating_sub(output.height as usize) as u16;
+        Paragraph::new(lines).scroll((scroll, 0)).render(output, buf);
+
+        Line::from(vec![
+            Span::from("> ").fg(Color::Yellow),
+            Span::from(state.input.as_str()),
+            Span::from("_").slow_blink(),
+        ]).render(input, buf);
+    }
+}
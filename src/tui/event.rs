@@ -2,10 +2,17 @@ use color_eyre::eyre::OptionExt;
 use futures::{FutureExt, StreamExt};
 use ratatui::crossterm::event::Event as CrosstermEvent;
 use std::time::Duration;
-use tokio::sync::mpsc;
-use crate::server::ServerError;
-use crate::usb::{Command, GCodeError};
-use crate::websocket::ClientError;
+use tokio::sync::{broadcast, mpsc, oneshot};
+use inti_e3m::server::ServerError;
+use inti_e3m::command::{Command, Source};
+use inti_e3m::usb::GCodeError;
+use inti_e3m::config::Config;
+use inti_e3m::pipeline::Stage;
+use crate::tui::app::Snapshot;
+use inti_e3m::websocket::ClientError;
+use inti_e3m::telemetry::{Telemetry, TelemetrySender};
+#[cfg(unix)]
+use inti_e3m::control::{ControlRequest, ControlSender};
 
 /// The frequency at which tick events are emitted.
 const TICK_FPS: f64 = 30.0;
@@ -33,21 +40,28 @@ pub enum Event {
 ///
 /// You can extend this enum with your own custom events.
 #[derive(Debug)]
+// the server/stage/snapshot events are only sent by the HTTP API and web UI so far
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
 pub enum AppEvent {
     /// Quit the application.
     Quit,
-    /// printer command
-    Command(Command),
-    /// Display GCode bar
-    GCode(String),
+    /// printer command, and who asked for it
+    Command(Source, Command),
+    /// raw G-code typed into the console
+    Console(String),
+    /// start the server if it's stopped, stop it if it's running
     Server,
-    ServerError(ErrorKind)
-}
-
-#[derive(Debug)]
-pub enum ErrorKind {
-    Websocket(String),
-    GCode(String)
+    StartServer,
+    StopServer,
+    /// add a pipeline stage to the profile, or replace the one of the same kind. applies live.
+    SetStage(Stage),
+    /// current state, for the HTTP API
+    Snapshot(oneshot::Sender<Snapshot>),
+    GetConfig(oneshot::Sender<Config>),
+    /// replace the whole config, e.g. from the control socket
+    SetConfig(Box<Config>),
+    /// something the server reported
+    Telemetry(Telemetry),
 }
 
 /// Terminal event handler.
@@ -57,6 +71,11 @@ pub struct EventHandler {
     pub sender: mpsc::UnboundedSender<Event>,
     /// Event receiver channel.
     receiver: mpsc::UnboundedReceiver<Event>,
+    /// a copy of all server telemetry, for anything outside the TUI that wants to follow along
+    telemetry: broadcast::Sender<Telemetry>,
+    /// for the control socket, its requests turn up here as app events
+    #[cfg(unix)]
+    control: ControlSender,
 }
 
 impl EventHandler {
@@ -65,7 +84,30 @@ impl EventHandler {
         let (sender, receiver) = mpsc::unbounded_channel();
         let actor = EventTask::new(sender.clone());
         tokio::spawn(async { actor.run().await });
-        Self { sender, receiver }
+        let (telemetry, _) = broadcast::channel(256);
+        #[cfg(unix)]
+        let control = {
+            let (control, mut rx) = mpsc::unbounded_channel();
+            let sender = sender.clone();
+            tokio::spawn(async move {
+                while let Some(request) = rx.recv().await {
+                    let event = match request {
+                        ControlRequest::Command(command) => AppEvent::Command(Source::Remote, command),
+                        ControlRequest::GetConfig(reply) => AppEvent::GetConfig(reply),
+                        ControlRequest::SetConfig(config) => AppEvent::SetConfig(config),
+                    };
+                    if sender.send(Event::App(event)).is_err() { break; }
+                }
+            });
+            control
+        };
+        Self {
+            sender,
+            receiver,
+            telemetry,
+            #[cfg(unix)]
+            control,
+        }
     }
 
     /// Receives an event from the sender.
@@ -84,6 +126,33 @@ impl EventHandler {
             .ok_or_eyre("Failed to receive event")
     }
 
+    /// A sender to hand the server, its telemetry turns up here as [`AppEvent::Telemetry`].
+    pub fn telemetry_sender(&self) -> TelemetrySender {
+        let (telemetry, mut rx) = mpsc::unbounded_channel::<Telemetry>();
+        let sender = self.sender.clone();
+        let broadcast = self.telemetry.clone();
+        tokio::spawn(async move {
+            while let Some(t) = rx.recv().await {
+                // nobody listening is fine
+                let _ = broadcast.send(t.clone());
+                if sender.send(Event::App(AppEvent::Telemetry(t))).is_err() { break; }
+            }
+        });
+        telemetry
+    }
+
+    /// Subscribe to this to follow the server's telemetry, slow receivers miss out.
+    #[cfg(any(unix, feature = "http"))]
+    pub fn telemetry_broadcast(&self) -> broadcast::Sender<Telemetry> {
+        self.telemetry.clone()
+    }
+
+    /// Where the control socket sends what scripts ask for.
+    #[cfg(unix)]
+    pub fn control_sender(&self) -> &ControlSender {
+        &self.control
+    }
+
     /// Queue an app event to be sent to the event receiver.
     ///
     /// This is useful for sending events to the event handler which will be processed by the next
@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Rect};
+use ratatui::prelude::Widget;
+use ratatui::style::{Color, Stylize};
+use ratatui::widgets::{Block, BorderType, Cell, Row, StatefulWidget, Table};
+use inti_e3m::telemetry::SentGCode;
+use inti_e3m::command::Source;
+
+/// How many lines are kept.
+const MAX_ENTRIES: usize = 500;
+
+/// Scrollable list of every line sent to the printer and what it said back.
+pub struct History;
+
+#[derive(Debug)]
+pub struct HistoryEntry {
+    pub sent: SentGCode,
+    pub reply: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct GCodeHistory {
+    entries: VecDeque<HistoryEntry>,
+    /// while paused only lines up to this id are shown, replies still fill in
+    frozen_at: Option<u64>,
+    /// only show lines from this source
+    pub filter: Option<Source>,
+    /// lines scrolled up from the newest
+    scroll: usize,
+}
+
+impl GCodeHistory {
+    pub fn push(&mut self, sent: SentGCode) {
+        self.entries.push_back(HistoryEntry { sent, reply: None });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn reply(&mut self, id: u64, reply: String) {
+        // replies come back in order so this is nearly always near the end
+        if let Some(entry) = self.entries.iter_mut().rev().find(|e| e.sent.id == id) {
+            entry.reply = Some(reply);
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.frozen_at = match self.frozen_at {
+            Some(_) => None,
+            None => Some(self.entries.back().map_or(0, |e| e.sent.id)),
+        };
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.frozen_at.is_some()
+    }
+
+    /// All -> each source -> All
+    pub fn cycle_filter(&mut self) {
+        self.filter = match self.filter {
+            None => Some(Source::ALL[0]),
+            Some(source) => Source::ALL.iter()
+                .position(|s| *s == source)
+                .and_then(|i| Source::ALL.get(i + 1))
+                .copied(),
+        };
+        self.scroll = 0;
+    }
+
+    pub fn scroll_up(&mut self, lines: usize) {
+        self.scroll = (self.scroll + lines).min(self.entries.len().saturating_sub(1));
+    }
+
+    pub fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    fn visible(&self) -> impl DoubleEndedIterator<Item = &HistoryEntry> {
+        self.entries.iter()
+            .filter(|e| self.frozen_at.is_none_or(|id| e.sent.id <= id))
+            .filter(|e| self.filter.is_none_or(|source| e.sent.source == source))
+    }
+}
+
+impl StatefulWidget for History {
+    type State = GCodeHistory;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let mut title = format!("G-code [{}]", state.filter.map_or("All".to_string(), |s| s.to_string()));
+        if state.is_paused() { title.push_str(" PAUSED"); }
+        let block = Block::bordered()
+            .title(title)
+            .title_bottom(" Space pause  v filter  PgUp/PgDn scroll ")
+            .border_type(BorderType::Rounded);
+
+        // newest at the bottom, so take from the end
+        let height = block.inner(area).height as usize;
+        let mut rows: Vec<Row> = state.visible()
+            .rev()
+            .skip(state.scroll)
+            .take(height)
+            .map(|entry| Row::new(vec![
+                Cell::from(entry.sent.sent_at.format("%H:%M:%S%.3f").to_string()),
+                Cell::from(entry.sent.source.to_string()).fg(Color::Cyan),
+                Cell::from(entry.sent.line.clone()),
+                match &entry.reply {
+                    Some(reply) => Cell::from(reply.clone()).fg(Color::Green),
+                    None => Cell::from("...").fg(Color::DarkGray),
+                },
+            ]))
+            .collect();
+        rows.reverse();
+
+        let table = Table::new(rows, [Constraint::Length(12), Constraint::Length(8), Constraint::Min(20), Constraint::Min(10)])
+            .block(block);
+        Widget::render(table, area, buf);
+    }
+}
@@ -0,0 +1,198 @@
+//! Local REST API, for scripts and phones on the LAN.
+//!
+//! Every request is turned into an [`AppEvent`] and goes through the same paths as the keyboard,
+//! so the TUI always shows what's going on. The web UI (see [`crate::tui::web`]) is served from `/`.
+//!
+//! Anything that changes state needs `Content-Type: application/json` and, if there's an
+//! `Origin`, one matching this server. Otherwise any page open in a browser could post a form here.
+//! Every request also needs a `Host` that's localhost, an IP address or the name we're bound to,
+//! so a page can't point its own domain at us (DNS rebinding) and read or change anything.
+
+use std::net::IpAddr;
+use axum::extract::{Path, Request, State};
+use axum::http::header::{CONTENT_TYPE, HOST, ORIGIN};
+use axum::http::{HeaderMap, Method, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use inti_e3m::config::{Config, HttpConfig};
+use inti_e3m::pipeline::Stage;
+use inti_e3m::command::{Command, Source};
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use crate::tui::app::Snapshot;
+use crate::tui::event::{AppEvent, Event, EventHandler};
+use crate::tui::web;
+
+pub(crate) type Events = mpsc::UnboundedSender<Event>;
+
+/// The running listeners, restarted whenever its config changes.
+#[derive(Debug, Default)]
+pub struct HttpServer {
+    running: Option<(HttpConfig, JoinHandle<()>)>,
+}
+
+impl HttpServer {
+    /// Start, stop or rebind to match `config`. Does nothing if it already matches.
+    pub fn update(&mut self, config: &HttpConfig, events: &EventHandler) {
+        if self.running.as_ref().is_some_and(|(running, _)| running == config) { return; }
+        if let Some((_, handle)) = self.running.take() {
+            handle.abort();
+            log::info!("HTTP API and web UI stopped");
+        }
+        if config.enabled {
+            let api = serve(config.bind.clone(), events.sender.clone());
+            let ui = web::serve_socket(config.websocket_bind.clone(), config.bind.clone(), events.sender.clone(), events.telemetry_broadcast());
+            // one task so aborting it stops both
+            self.running = Some((config.clone(), tokio::spawn(async { tokio::join!(api, ui); })));
+        }
+    }
+}
+
+async fn serve(bind: String, events: Events) {
+    let listener = match tokio::net::TcpListener::bind(&bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("HTTP API could not bind to {bind}: {e}");
+            return;
+        }
+    };
+    log::info!("HTTP API listening on http://{bind}");
+    if let Err(e) = axum::serve(listener, router(events, bind)).await {
+        log::error!("HTTP API stopped: {e}");
+    }
+}
+
+fn router(events: Events, bind: String) -> Router {
+    Router::new()
+        .route("/", get(web::page))
+        .route("/status", get(status))
+        .route("/config", get(config))
+        .route("/server/start", post(|State(events): State<Events>| send(events, AppEvent::StartServer)))
+        .route("/server/stop", post(|State(events): State<Events>| send(events, AppEvent::StopServer)))
+        .route("/command/{name}", post(command))
+        .route("/intensity", put(intensity))
+        .route("/range", put(range))
+        .layer(middleware::from_fn_with_state(bind, same_origin_json))
+        .with_state(events)
+}
+
+/// Browsers won't send a JSON content type or a foreign `Origin` without asking (CORS) first, and
+/// we never say yes. Scripts just have to set the header.
+async fn same_origin_json(State(bind): State<String>, request: Request, next: Next) -> Result<Response, StatusCode> {
+    let headers = request.headers();
+    let host = header(headers, HOST).unwrap_or_default();
+    if !allowed_host(host, &bind) {
+        log::warn!("HTTP API refused a request for host {host}");
+        return Err(StatusCode::FORBIDDEN);
+    }
+    if request.method() != Method::GET {
+        let json = header(headers, CONTENT_TYPE).is_some_and(|t| t.starts_with("application/json"));
+        if !json { return Err(StatusCode::UNSUPPORTED_MEDIA_TYPE); }
+        if let Some(origin) = header(headers, ORIGIN) && origin_authority(origin) != Some(host) {
+            log::warn!("HTTP API refused a request from {origin}");
+            return Err(StatusCode::FORBIDDEN);
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+pub(crate) fn header(headers: &HeaderMap, name: impl axum::http::header::AsHeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Whether `host` (a `Host` header) is one no one else can point at us: localhost, an IP address,
+/// or the name in `bind`.
+pub(crate) fn allowed_host(host: &str, bind: &str) -> bool {
+    let name = hostname(host);
+    !name.is_empty() && (name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok() || name.eq_ignore_ascii_case(hostname(bind)))
+}
+
+/// `127.0.0.1:8080` -> `127.0.0.1`, `[::1]:8080` -> `::1`
+pub(crate) fn hostname(authority: &str) -> &str {
+    match authority.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or(rest),
+        None => authority.rsplit_once(':').map_or(authority, |(host, _)| host),
+    }
+}
+
+/// `http://127.0.0.1:8080` -> `127.0.0.1:8080`
+pub(crate) fn origin_authority(origin: &str) -> Option<&str> {
+    origin.strip_prefix("http://").or_else(|| origin.strip_prefix("https://"))
+}
+
+async fn send(events: Events, event: AppEvent) -> StatusCode {
+    match events.send(Event::App(event)) {
+        Ok(()) => StatusCode::ACCEPTED,
+        // the app is shutting down
+        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+    }
+}
+
+pub(crate) async fn snapshot(events: &Events) -> Result<Snapshot, StatusCode> {
+    let (reply, rx) = oneshot::channel();
+    events.send(Event::App(AppEvent::Snapshot(reply))).map_err(|_| StatusCode::SERVICE_UNAVAILABLE)?;
+    rx.await.map_err(|_| StatusCode::SERVICE_UNAVAILABLE)
+}
+
+async fn status(State(events): State<Events>) -> Result<Json<Snapshot>, StatusCode> {
+    Ok(Json(snapshot(&events).await?))
+}
+
+async fn config(State(events): State<Events>) -> Result<Json<Config>, StatusCode> {
+    Ok(Json(snapshot(&events).await?.config))
+}
+
+/// `home`, `pause`, `park`, `resume` or `halt`
+async fn command(State(events): State<Events>, Path(name): Path<String>) -> StatusCode {
+    let command = match name.as_str() {
+        "home" => Command::Home,
+        "pause" => Command::Pause,
+        "park" => Command::Park,
+        "resume" => Command::Resume,
+        "halt" => Command::Halt,
+        _ => return StatusCode::NOT_FOUND,
+    };
+    send(events, AppEvent::Command(Source::Remote, command)).await
+}
+
+#[derive(Deserialize)]
+struct Intensity {
+    value: f32,
+}
+
+/// `{"value": 0.8}`, see [`Stage::Intensity`]
+async fn intensity(State(events): State<Events>, Json(Intensity { value }): Json<Intensity>) -> StatusCode {
+    if !value.is_finite() || value < 0.0 { return StatusCode::UNPROCESSABLE_ENTITY; }
+    send(events, AppEvent::SetStage(Stage::Intensity(value))).await
+}
+
+#[derive(Deserialize)]
+struct Range {
+    min: f32,
+    max: f32,
+}
+
+/// `{"min": 10, "max": 90}` in percent of the stroke, see [`Stage::Remap`]
+async fn range(State(events): State<Events>, Json(Range { min, max }): Json<Range>) -> StatusCode {
+    if !(0.0..=100.0).contains(&min) || !(0.0..=100.0).contains(&max) { return StatusCode::UNPROCESSABLE_ENTITY; }
+    send(events, AppEvent::SetStage(Stage::Remap { min, max })).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_that_cant_be_rebound() {
+        for host in ["127.0.0.1:8080", "localhost:8080", "LOCALHOST", "192.168.1.20:8080", "[::1]:8080"] {
+            assert!(allowed_host(host, "127.0.0.1:8080"), "{host} should be allowed");
+        }
+        assert!(allowed_host("printer.local:8080", "printer.local:8080"));
+        for host in ["evil.example:8080", "evil.example", "", "localhost.evil.example:8080"] {
+            assert!(!allowed_host(host, "0.0.0.0:8080"), "{host} should be refused");
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+use inti_e3m::telemetry::Latency;
+
+/// How many recent movements the percentiles are over.
+const WINDOW: usize = 500;
+
+/// Latency of the last [`WINDOW`] acknowledged movements.
+#[derive(Debug, Default)]
+pub struct LatencyHistory {
+    samples: VecDeque<Latency>,
+}
+
+impl LatencyHistory {
+    pub fn push(&mut self, latency: Latency) {
+        if self.samples.len() == WINDOW { self.samples.pop_front(); }
+        self.samples.push_back(latency);
+    }
+
+    /// e.g. ` queue 2/5/9ms  printer 18/40/62ms (p50/p95/p99) `, `None` before the first movement
+    pub fn summary(&self) -> Option<String> {
+        if self.samples.is_empty() { return None; }
+        let queued = self.percentiles(|l| l.queued);
+        let printer = self.percentiles(|l| l.printer);
+        Some(format!(" queue {queued}ms  printer {printer}ms (p50/p95/p99) "))
+    }
+
+    fn percentiles(&self, leg: impl Fn(&Latency) -> Duration) -> String {
+        let mut sorted: Vec<Duration> = self.samples.iter().map(leg).collect();
+        sorted.sort();
+        [0.5, 0.95, 0.99].map(|p| {
+            let i = ((sorted.len() - 1) as f64 * p).round() as usize;
+            sorted[i].as_millis().to_string()
+        }).join("/")
+    }
+}
@@ -1,12 +1,18 @@
 use ratatui::buffer::Buffer;
 use ratatui::layout::{Constraint, Flex, Layout, Rect};
 use ratatui::prelude::{Stylize, Widget};
+use ratatui::style::Color;
 use ratatui::text::{Line, Span};
-use ratatui::widgets::{Block, Clear, Paragraph, StatefulWidget, Wrap};
+use ratatui::widgets::{Block, Clear, List, ListState, Paragraph, StatefulWidget, Wrap};
+use inti_e3m::telemetry::{ErrorKind, ErrorReport};
 
 pub struct Popup;
+pub struct SelectPopup;
+/// Details of the last server error, opened with `e`.
+pub struct ErrorPopup;
 
-#[derive(Debug)]
+/// What a popup will accept, with inclusive bounds (value bounds for numbers, length for strings).
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum DataType {
     Integer(i32, i32),
     UnsignedInteger(u32, u32),
@@ -16,10 +22,49 @@ pub enum DataType {
 impl DataType {
     pub fn get_max_chars(&self) -> usize {
          match self {
-            DataType::Integer(lower, upper) => { upper.to_string().len() }
-            DataType::UnsignedInteger(lower, upper) => { upper.to_string().len() }
-            DataType::String(min, max) => *max
+            DataType::Integer(lower, upper) => { lower.to_string().len().max(upper.to_string().len()) }
+            DataType::UnsignedInteger(_, upper) => { upper.to_string().len() }
+            DataType::String(_, max) => *max
+        }
+    }
+
+    /// Whether `c` can be typed after `text`. Stops letters ending up in number fields.
+    pub fn accepts(&self, text: &str, c: char) -> bool {
+        match self {
+            DataType::Integer(lower, _) => c.is_ascii_digit() || (c == '-' && text.is_empty() && *lower < 0),
+            DataType::UnsignedInteger(..) => c.is_ascii_digit(),
+            DataType::String(..) => !c.is_control(),
+        }
+    }
+
+    /// Keep only what could have been typed, e.g. "100 mm" -> "100" for a number.
+    pub fn filter(&self, text: &str) -> String {
+        let mut filtered = String::new();
+        for c in text.chars() {
+            if filtered.chars().count() < self.get_max_chars() && self.accepts(&filtered, c) {
+                filtered.push(c);
+            }
         }
+        filtered
+    }
+
+    /// Check `text` parses and is in bounds, returning a message for the popup if not.
+    pub fn validate(&self, text: &str) -> Result<(), String> {
+        match self {
+            DataType::Integer(lower, upper) => {
+                let n: i32 = text.parse().map_err(|_| "Not a number".to_string())?;
+                if n < *lower || n > *upper { return Err(format!("Must be {lower} to {upper}")); }
+            }
+            DataType::UnsignedInteger(lower, upper) => {
+                let n: u32 = text.parse().map_err(|_| "Not a number".to_string())?;
+                if n < *lower || n > *upper { return Err(format!("Must be {lower} to {upper}")); }
+            }
+            DataType::String(min, max) => {
+                let len = text.chars().count();
+                if len < *min || len > *max { return Err(format!("Must be {min} to {max} characters")); }
+            }
+        }
+        Ok(())
     }
 }
 
@@ -29,6 +74,8 @@ pub struct PopupState {
     pub(crate) description: Option<String>,
     pub(crate) entered_text: String,
     pub(crate) data: DataType,
+    /// shown under the entry when the last submit was rejected
+    pub(crate) error: Option<String>,
 }
 
 impl StatefulWidget for Popup {
@@ -36,7 +83,7 @@ impl StatefulWidget for Popup {
 
     fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
         let entry_width = state.data.get_max_chars();
-        let entry_width= (entry_width + 6).min(20) as u16;
+        let entry_width= (entry_width + 6).min(60) as u16;
 
         let (paragraph, additional_height) = if let Some(description) = &state.description {
             let paragraph = Paragraph::new(description.as_str())
@@ -46,20 +93,34 @@ impl StatefulWidget for Popup {
             (Some(paragraph),lines)
         } else { (None,0) };
 
-        let area = popup_area(area, entry_width, (2+ 1 + 1 + additional_height) as u16);
+        let error_height = if state.error.is_some() { 1 } else { 0 };
+        let area = popup_area(area, entry_width, (2+ 1 + 1 + error_height + additional_height) as u16);
         Clear.render(area, buf);
 
         let block = Block::bordered().title(state.header.as_str());
         block.render(area, buf);
 
-        let [paragraph_area, entry_area, controls_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1), Constraint::Length(1)])
-            .vertical_margin(1).horizontal_margin(2).areas(area);
+        let [paragraph_area, entry_area, error_area, controls_area] = Layout::vertical([
+            Constraint::Min(0), Constraint::Length(1), Constraint::Length(error_height as u16), Constraint::Length(1)
+        ]).vertical_margin(1).horizontal_margin(2).areas(area);
 
         if let Some(paragraph) = paragraph {
             paragraph.render(paragraph_area, buf);
         }
-        let line = Line::from(state.entered_text.as_str()).slow_blink();
-        line.render(entry_area, buf);
+        // longer than the popup scrolls, keeping the end where the typing happens in view
+        let visible = entry_area.width as usize;
+        let typed = state.entered_text.chars().count();
+        let text = if typed > visible {
+            let tail: String = state.entered_text.chars().skip(typed + 1 - visible).collect();
+            format!("…{tail}")
+        } else {
+            state.entered_text.clone()
+        };
+        Line::from(text).slow_blink().render(entry_area, buf);
+
+        if let Some(error) = &state.error {
+            Line::from(error.as_str()).fg(Color::Red).centered().render(error_area, buf);
+        }
 
         let controls = Line::from(vec![
             Span::from(" Cancel "), Span::from(" Esc "),
@@ -69,6 +130,120 @@ impl StatefulWidget for Popup {
     }
 }
 
+/// One entry in a [`SelectPopup`].
+#[derive(Debug, Clone)]
+pub struct SelectOption {
+    pub label: String,
+    /// what gets handed to the config option. `None` means "let me type it in instead".
+    pub value: Option<String>,
+}
+
+impl SelectOption {
+    pub fn new(label: impl Into<String>, value: impl Into<String>) -> Self {
+        SelectOption { label: label.into(), value: Some(value.into()) }
+    }
+    pub fn custom(label: impl Into<String>) -> Self {
+        SelectOption { label: label.into(), value: None }
+    }
+}
+
+#[derive(Debug)]
+pub struct SelectPopupState {
+    pub(crate) header: String,
+    pub(crate) options: Vec<SelectOption>,
+    pub(crate) list_state: ListState,
+}
+
+impl SelectPopupState {
+    /// Starts on whichever option has the value `current`, if any.
+    pub fn new(header: String, options: Vec<SelectOption>, current: &str) -> Self {
+        let selected = options.iter()
+            .position(|o| o.value.as_deref() == Some(current))
+            .unwrap_or(0);
+        SelectPopupState { header, options, list_state: ListState::default().with_selected(Some(selected)) }
+    }
+    pub fn next(&mut self) {
+        if self.options.is_empty() { return; }
+        let i = self.list_state.selected().map_or(0, |i| (i + 1) % self.options.len());
+        self.list_state.select(Some(i));
+    }
+    pub fn previous(&mut self) {
+        if self.options.is_empty() { return; }
+        let i = self.list_state.selected().map_or(0, |i| i.checked_sub(1).unwrap_or(self.options.len() - 1));
+        self.list_state.select(Some(i));
+    }
+    pub fn selected(&self) -> Option<&SelectOption> {
+        self.list_state.selected().and_then(|i| self.options.get(i))
+    }
+}
+
+impl StatefulWidget for SelectPopup {
+    type State = SelectPopupState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let height = (state.options.len().max(1) + 2 + 1).min(area.height as usize) as u16;
+        let area = popup_area(area, 50, height);
+        Clear.render(area, buf);
+
+        let [list_area, controls_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(area);
+        let list = if state.options.is_empty() {
+            List::new(["Nothing found"])
+        } else {
+            List::new(state.options.iter().map(|o| o.label.as_str()))
+        };
+        let list = list
+            .block(Block::bordered().title(state.header.as_str()))
+            .highlight_style((Color::Green, Color::Blue))
+            .highlight_symbol("> ");
+        StatefulWidget::render(list, list_area, buf, &mut state.list_state);
+
+        let controls = Line::from(vec![
+            Span::from(" Cancel "), Span::from(" Esc "),
+            Span::from(" Move "), Span::from(" ↑/↓ "),
+            Span::from(" Select "), Span::from(" Enter "),
+        ]).centered();
+        controls.render(controls_area, buf);
+    }
+}
+
+impl StatefulWidget for ErrorPopup {
+    type State = ErrorKind;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let side = match state {
+            ErrorKind::Input(_) => "Websocket",
+            ErrorKind::Backend(_) => "USB",
+        };
+        let ErrorReport { code, summary, chain, hint, at } = state.report();
+
+        let mut lines = vec![
+            Line::from(vec![Span::from(format!("{code} ")).fg(Color::Red).bold(), Span::from(summary.as_str())]),
+            Line::from(format!("{side} stopped at {}", at.format("%H:%M:%S"))).fg(Color::Gray),
+            Line::from(""),
+        ];
+        for (i, cause) in chain.iter().enumerate() {
+            let prefix = if i == 0 { "" } else { "caused by: " };
+            lines.push(Line::from(format!("{prefix}{cause}")));
+        }
+        if let Some(hint) = hint {
+            lines.push(Line::from(""));
+            lines.push(Line::from(vec![Span::from("Try: ").fg(Color::Green), Span::from(*hint)]));
+        }
+
+        let width = 70.min(area.width);
+        let paragraph = Paragraph::new(lines).wrap(Wrap { trim: false });
+        let height = (paragraph.line_count(width.saturating_sub(4)) as u16 + 2 + 1).min(area.height);
+        let area = popup_area(area, width, height);
+        Clear.render(area, buf);
+
+        Block::bordered().title(" Error details ").fg(Color::Red).render(area, buf);
+        let [text_area, controls_area] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)])
+            .vertical_margin(1).horizontal_margin(2).areas(area);
+        paragraph.render(text_area, buf);
+        Line::from(vec![Span::from(" Close "), Span::from(" Esc/e ")]).centered().render(controls_area, buf);
+    }
+}
+
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn popup_area(area: Rect, width: u16, height: u16) -> Rect {
     let vertical = Layout::vertical([Constraint::Length(height)]).flex(Flex::Center);
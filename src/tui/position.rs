@@ -0,0 +1,91 @@
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::prelude::Widget;
+use ratatui::style::{Color, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, BorderType, Gauge, StatefulWidget};
+use inti_e3m::firmware::Position;
+
+/// Commanded vs reported carriage position, so lag and missed steps are visible.
+pub struct PositionGauge {
+    /// X at the bottom and top of the stroke, in mm
+    pub range: (f32, f32),
+}
+
+#[derive(Debug, Default)]
+pub struct PositionState {
+    pub commanded: Option<f32>,
+    pub reported: Option<Position>,
+}
+
+impl PositionGauge {
+    /// How far up the stroke `x` is, 0 to 1. An empty range (or a nonsense x) is 0 rather than a NaN
+    /// for `Gauge` to choke on.
+    fn ratio(&self, x: Option<f32>) -> f32 {
+        let (bottom, top) = self.range;
+        match x {
+            Some(x) if top > bottom => {
+                let ratio = (x - bottom) / (top - bottom);
+                if ratio.is_finite() { ratio.clamp(0.0, 1.0) } else { 0.0 }
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn gauge(&self, label: &str, x: Option<f32>, color: Color) -> Gauge<'_> {
+        let ratio = self.ratio(x);
+        let text = match x {
+            Some(x) => format!("{label} {x:.1} mm"),
+            None => format!("{label} -"),
+        };
+        Gauge::default()
+            .ratio(ratio as f64)
+            .label(text)
+            .gauge_style((color, Color::DarkGray))
+    }
+}
+
+impl StatefulWidget for PositionGauge {
+    type State = PositionState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let block = Block::bordered()
+            .title("Position")
+            .border_type(BorderType::Rounded);
+        let inner = block.inner(area);
+        block.render(area, buf);
+
+        let [commanded, reported, lag] = Layout::vertical([Constraint::Length(1); 3]).areas(inner);
+        let reported_x = state.reported.map(|p| p.x);
+        self.gauge("Commanded", state.commanded, Color::Cyan).render(commanded, buf);
+        self.gauge("Actual", reported_x, Color::Green).render(reported, buf);
+
+        let lag_line = match (state.commanded, reported_x) {
+            (Some(commanded), Some(reported)) => Line::from(format!("Lag {:.1} mm", (commanded - reported).abs())),
+            _ => Line::from("Lag -").fg(Color::Gray),
+        };
+        lag_line.render(lag, buf);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ratio_is_within_the_stroke() {
+        let gauge = PositionGauge { range: (100.0, 200.0) };
+        assert_eq!(gauge.ratio(Some(150.0)), 0.5);
+        assert_eq!(gauge.ratio(Some(50.0)), 0.0);
+        assert_eq!(gauge.ratio(Some(250.0)), 1.0);
+        assert_eq!(gauge.ratio(None), 0.0);
+    }
+
+    #[test]
+    fn empty_range_or_nan_is_zero() {
+        assert_eq!(PositionGauge { range: (200.0, 200.0) }.ratio(Some(200.0)), 0.0);
+        assert_eq!(PositionGauge { range: (200.0, 100.0) }.ratio(Some(150.0)), 0.0);
+        assert_eq!(PositionGauge { range: (100.0, 200.0) }.ratio(Some(f32::NAN)), 0.0);
+        assert_eq!(PositionGauge { range: (100.0, 200.0) }.ratio(Some(f32::INFINITY)), 0.0);
+    }
+}
@@ -0,0 +1,141 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::prelude::Widget;
+use ratatui::style::{Color, Style};
+use ratatui::symbols::Marker;
+use ratatui::widgets::{Axis, Block, BorderType, Chart, Dataset, GraphType, StatefulWidget};
+use ratatui::text::Line;
+use inti_e3m::queue::QueueStats;
+use crate::tui::latency::LatencyHistory;
+use inti_e3m::telemetry::MotionMarker;
+
+/// How far back the chart goes.
+const HISTORY: Duration = Duration::from_secs(10);
+
+/// Scrolling plot of the last [`HISTORY`] of motion.
+pub struct StrokeChart<'a> {
+    /// X at the bottom and top of the stroke, in mm
+    pub range: (f32, f32),
+    /// counters from the running server's queue
+    pub queue: Option<&'a QueueStats>,
+    pub latency: &'a LatencyHistory,
+}
+
+#[derive(Debug)]
+pub struct StrokeHistory {
+    commanded: VecDeque<(Instant, f32)>,
+    reported: VecDeque<(Instant, f32)>,
+    markers: VecDeque<(Instant, MotionMarker, f32)>,
+    /// also plot where the firmware says the carriage is
+    pub show_reported: bool,
+}
+
+impl Default for StrokeHistory {
+    fn default() -> Self {
+        StrokeHistory {
+            commanded: VecDeque::new(),
+            reported: VecDeque::new(),
+            markers: VecDeque::new(),
+            show_reported: true,
+        }
+    }
+}
+
+impl StrokeHistory {
+    pub fn push_commanded(&mut self, x: f32) {
+        self.commanded.push_back((Instant::now(), x));
+        self.trim();
+    }
+    pub fn push_reported(&mut self, x: f32) {
+        self.reported.push_back((Instant::now(), x));
+        self.trim();
+    }
+    pub fn push_marker(&mut self, marker: MotionMarker, x: f32) {
+        self.markers.push_back((Instant::now(), marker, x));
+        self.trim();
+    }
+
+    fn trim(&mut self) {
+        let Some(cutoff) = Instant::now().checked_sub(HISTORY) else { return };
+        while self.commanded.front().is_some_and(|(t, _)| *t < cutoff) { self.commanded.pop_front(); }
+        while self.reported.front().is_some_and(|(t, _)| *t < cutoff) { self.reported.pop_front(); }
+        while self.markers.front().is_some_and(|(t, ..)| *t < cutoff) { self.markers.pop_front(); }
+    }
+}
+
+/// seconds before now, negative so the newest point is on the right
+fn age(now: Instant, t: Instant) -> f64 {
+    -(now.duration_since(t).as_secs_f64())
+}
+
+impl StatefulWidget for StrokeChart<'_> {
+    type State = StrokeHistory;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let now = Instant::now();
+        state.trim();
+
+        let commanded: Vec<(f64, f64)> = state.commanded.iter().map(|(t, x)| (age(now, *t), *x as f64)).collect();
+        let reported: Vec<(f64, f64)> = state.reported.iter().map(|(t, x)| (age(now, *t), *x as f64)).collect();
+        let marker_points = |kind: MotionMarker| -> Vec<(f64, f64)> {
+            state.markers.iter()
+                .filter(|(_, marker, _)| *marker == kind)
+                .map(|(t, _, x)| (age(now, *t), *x as f64))
+                .collect()
+        };
+        let limited = marker_points(MotionMarker::Limited);
+        let dropped = marker_points(MotionMarker::Dropped);
+
+        let mut datasets = vec![
+            Dataset::default()
+                .name("Commanded")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Cyan))
+                .data(&commanded),
+        ];
+        if state.show_reported {
+            datasets.push(Dataset::default()
+                .name("Actual")
+                .marker(Marker::Braille)
+                .graph_type(GraphType::Line)
+                .style(Style::default().fg(Color::Green))
+                .data(&reported));
+        }
+        datasets.push(Dataset::default()
+            .name("Limited")
+            .marker(Marker::Dot)
+            .graph_type(GraphType::Scatter)
+            .style(Style::default().fg(Color::Yellow))
+            .data(&limited));
+        datasets.push(Dataset::default()
+            .name("Dropped")
+            .marker(Marker::Dot)
+            .graph_type(GraphType::Scatter)
+            .style(Style::default().fg(Color::Red))
+            .data(&dropped));
+
+        let (bottom, top) = self.range;
+        let history = HISTORY.as_secs_f64();
+        let mut block = Block::bordered()
+            .title("Stroke (f: toggle actual)")
+            .border_type(BorderType::Rounded);
+        if let Some(queue) = self.queue {
+            block = block.title_bottom(format!(" sent {}  coalesced {} ", queue.delivered(), queue.coalesced()));
+        }
+        if let Some(latency) = self.latency.summary() {
+            block = block.title_bottom(Line::from(latency).right_aligned());
+        }
+        Chart::new(datasets)
+            .block(block)
+            .x_axis(Axis::default()
+                .bounds([-history, 0.0])
+                .labels([format!("-{history:.0}s"), "now".to_string()]))
+            .y_axis(Axis::default()
+                .bounds([bottom as f64, top as f64])
+                .labels([format!("{bottom:.0}"), format!("{top:.0}")]))
+            .render(area, buf);
+    }
+}
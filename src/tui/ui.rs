@@ -1,8 +1,14 @@
-use crate::tui::app::App;
+use crate::tui::app::{App, Tab};
+use crate::tui::console::Console;
 use crate::tui::bar::{Bar, ServicesState};
-use crate::tui::popup::Popup;
+use crate::tui::popup::{ErrorPopup, Popup, SelectPopup};
+use crate::tui::position::PositionGauge;
+use crate::tui::stroke_chart::StrokeChart;
+use crate::tui::history::History;
 use ratatui::layout::{Constraint, Flex, Layout};
-use ratatui::widgets::{Row, Table};
+use ratatui::style::Stylize;
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Paragraph, Row, Table, Tabs};
 use ratatui::{layout::{Alignment, Rect}, style::Color, widgets::{Block, BorderType}, Frame};
 use tui_logger::TuiLoggerWidget;
 
@@ -10,7 +16,7 @@ impl App {
     pub(crate) fn draw(&mut self, frame: &mut Frame) {
         let layout = Layout::vertical([
             Constraint::Min(0),
-            Constraint::Length(1),
+            Constraint::Length(2),
         ]);
 
         let [main, bar] = layout.areas(frame.area());
@@ -20,18 +26,66 @@ impl App {
             .border_type(BorderType::Rounded), main);
 
         let [config, log] = Layout::horizontal([Constraint::Length(60), Constraint::Min(10)]).margin(2).flex(Flex::Center).areas(main);
+        let [config, firmware, position] = Layout::vertical([Constraint::Min(0), Constraint::Length(6), Constraint::Length(5)]).areas(config);
         self.render_table(frame,config);
+        self.render_firmware(frame, firmware);
+        let machine = &self.config.profile().machine_config;
+        let range = (machine.stroke_base(), machine.throw as f32);
+        frame.render_stateful_widget(PositionGauge { range }, position, &mut self.services_state.position);
+
+        let [chart, history, log] = Layout::vertical([Constraint::Length(14), Constraint::Fill(1), Constraint::Fill(1)]).areas(log);
+        let queue = self.server.as_ref().map(|s| s.queue_stats.as_ref());
+        frame.render_stateful_widget(StrokeChart { range, queue, latency: &self.services_state.latency }, chart, &mut self.services_state.history);
+        frame.render_stateful_widget(History, history, &mut self.services_state.gcode_history);
+        self.services_state.recorded = self.server.as_ref().and_then(|s| s.recorder.recorded());
         frame.render_stateful_widget(Bar, bar, &mut self.services_state);
 
-        frame.render_widget(
-            TuiLoggerWidget::default()
-                .block(Block::bordered().title("Log")),
-            log
-        );
+        let tabs = Tabs::new(["Log", "Console"])
+            .select(if self.tab == Tab::Log { 0 } else { 1 })
+            .highlight_style((Color::Yellow, Color::Blue));
+        let [tabs_area, log] = Layout::vertical([Constraint::Length(1), Constraint::Min(0)]).areas(log);
+        frame.render_widget(tabs, tabs_area);
+        let block = Block::bordered().title_bottom(" Tab switch ");
+        let inner = block.inner(log);
+        frame.render_widget(block, log);
+        match self.tab {
+            Tab::Log => frame.render_widget(TuiLoggerWidget::default(), inner),
+            Tab::Console => frame.render_stateful_widget(
+                Console, inner, &mut self.console
+            ),
+        }
 
         if let Some(popup) = self.popup_state.as_mut() {
             frame.render_stateful_widget(Popup, frame.area(), popup);
         }
+        if let Some(select) = self.select_state.as_mut() {
+            frame.render_stateful_widget(SelectPopup, frame.area(), select);
+        }
+        if let Some(error) = self.services_state.last_error.as_mut().filter(|_| self.error_popup) {
+            frame.render_stateful_widget(ErrorPopup, frame.area(), error);
+        }
+    }
+
+    fn render_firmware(&self, frame: &mut Frame, area: Rect) {
+        let block = Block::bordered()
+            .title("Firmware")
+            .border_type(BorderType::Rounded);
+        let lines = match &self.services_state.firmware {
+            None => vec![Line::from("Not connected")],
+            Some(firmware) => {
+                let cap = |name: &'static str, present: bool| {
+                    let (mark, color) = if present { ("✓", Color::Green) } else { ("✗", Color::Red) };
+                    Line::from(vec![Span::from(format!("{mark} ")).fg(color), Span::from(name)])
+                };
+                vec![
+                    Line::from(format!("{} ({} caps)", firmware.firmware_name, firmware.caps.len())),
+                    Line::from(firmware.kind.to_string()).fg(Color::Gray),
+                    cap("EMERGENCY_PARSER", firmware.emergency_parser()),
+                    cap("AUTOREPORT_POS", firmware.autoreport_pos()),
+                ]
+            }
+        };
+        frame.render_widget(Paragraph::new(lines).block(block), area);
     }
 
     fn render_table(&mut self, frame: &mut Frame, area: Rect) {
@@ -0,0 +1,153 @@
+//! A small web UI for a phone or tablet, served by the HTTP API.
+//!
+//! The page is baked into the binary and gets its live updates over its own websocket, on
+//! `HttpConfig::websocket_bind`. Buttons and sliders turn into the same [`AppEvent`]s as the keyboard.
+
+use axum::response::Html;
+use futures_util::{SinkExt, StreamExt};
+use inti_e3m::pipeline::Stage;
+use inti_e3m::telemetry::Telemetry;
+use inti_e3m::command::{Command, Source};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request as Handshake, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tokio_tungstenite::tungstenite::Message;
+use crate::tui::app::Snapshot;
+use crate::tui::event::{AppEvent, Event};
+use crate::tui::http::{allowed_host, header, origin_authority, snapshot, Events};
+
+const PAGE: &str = include_str!("web/index.html");
+/// how often the status part of the page is refreshed, positions are sent as they happen
+const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+
+pub async fn page() -> Html<&'static str> {
+    Html(PAGE)
+}
+
+/// What the page can ask for.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Start,
+    Stop,
+    Home,
+    Halt,
+    /// percent of the stroke, see [`Stage::Remap`]
+    Range { min: f32, max: f32 },
+    /// how much faster, `2` halves every duration, see [`Stage::Speed`]
+    SpeedScale { value: f32 },
+}
+
+impl Request {
+    fn into_event(self) -> AppEvent {
+        match self {
+            Request::Start => AppEvent::StartServer,
+            Request::Stop => AppEvent::StopServer,
+            Request::Home => AppEvent::Command(Source::Remote, Command::Home),
+            Request::Halt => AppEvent::Command(Source::Remote, Command::Halt),
+            Request::Range { min, max } => AppEvent::SetStage(Stage::Remap { min: min.clamp(0.0, 100.0), max: max.clamp(0.0, 100.0) }),
+            Request::SpeedScale { value } => AppEvent::SetStage(Stage::Speed(value.max(0.01))),
+        }
+    }
+}
+
+/// What the page gets told.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Update {
+    Status(Box<Snapshot>),
+    /// X we told the printer to go to, in mm
+    Commanded { x: f32 },
+    /// X the printer says it's at, in mm
+    Reported { x: f32 },
+}
+
+/// `page_bind` is where the page itself is served, only it gets to connect.
+pub async fn serve_socket(bind: String, page_bind: String, events: Events, telemetry: broadcast::Sender<Telemetry>) {
+    let listener = match TcpListener::bind(&bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::error!("Web UI could not bind to {bind}: {e}");
+            return;
+        }
+    };
+    log::info!("Web UI updates on ws://{bind}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => { tokio::spawn(client(stream, peer, page_bind.clone(), events.clone(), telemetry.subscribe())); }
+            Err(e) => log::warn!("Web UI could not accept a connection: {e}"),
+        }
+    }
+}
+
+async fn client(stream: TcpStream, peer: SocketAddr, page_bind: String, events: Events, mut telemetry: broadcast::Receiver<Telemetry>) {
+    // the error type is tungstenite's, not ours to shrink
+    #[allow(clippy::result_large_err)]
+    let check_origin = |request: &Handshake, response: Response| -> Result<Response, ErrorResponse> {
+        let headers = request.headers();
+        let host = header(headers, "host").unwrap_or_default();
+        // browsers always send an origin, anything else isn't a web page
+        let allowed = allowed_host(host, &page_bind) && header(headers, "origin").is_none_or(|origin| allowed_origin(origin, host, &page_bind));
+        if allowed { return Ok(response); }
+        log::warn!("Web UI refused a connection for {host} from {}", header(headers, "origin").unwrap_or("no origin"));
+        let mut refused = ErrorResponse::new(Some("Origin not allowed".to_string()));
+        *refused.status_mut() = StatusCode::FORBIDDEN;
+        Err(refused)
+    };
+    let websocket = match accept_hdr_async(stream, check_origin).await {
+        Ok(websocket) => websocket,
+        Err(e) => {
+            log::warn!("Web UI handshake with {peer} failed: {e}");
+            return;
+        }
+    };
+    log::info!("Web UI connected from {peer}");
+    let (mut outgoing, mut incoming) = websocket.split();
+    let mut status = tokio::time::interval(STATUS_INTERVAL);
+    loop {
+        let update = tokio::select! {
+            _ = status.tick() => match snapshot(&events).await {
+                Ok(snapshot) => Update::Status(Box::new(snapshot)),
+                // the app is going away
+                Err(_) => break,
+            },
+            telemetry = telemetry.recv() => match telemetry {
+                Ok(Telemetry::CommandedPosition(x)) => Update::Commanded { x },
+                Ok(Telemetry::ReportedPosition(position)) => Update::Reported { x: position.x },
+                Ok(_) | Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<Request>(text.as_str()) {
+                        Ok(request) => if events.send(Event::App(request.into_event())).is_err() { break },
+                        Err(e) => log::warn!("Web UI sent something odd: {e}"),
+                    }
+                    continue;
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => {
+                    log::warn!("Web UI connection from {peer} failed: {e}");
+                    break;
+                }
+            },
+        };
+        let json = serde_json::to_string(&update).expect("updates always serialize");
+        if outgoing.send(Message::text(json)).await.is_err() { break; }
+    }
+    log::info!("Web UI disconnected from {peer}");
+}
+
+/// The page has the same host as this socket (whatever name it was reached by) on `page_bind`'s port.
+fn allowed_origin(origin: &str, host: &str, page_bind: &str) -> bool {
+    let Some((origin_host, origin_port)) = origin_authority(origin).and_then(|a| a.rsplit_once(':')) else { return false };
+    let socket_host = host.rsplit_once(':').map_or(host, |(host, _)| host);
+    origin_host == socket_host && page_bind.rsplit_once(':').is_some_and(|(_, port)| port == origin_port)
+}
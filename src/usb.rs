@@ -1,40 +1,25 @@
+use std::collections::VecDeque;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::AsyncWriteExt;
-use tokio::sync::mpsc::{Receiver, UnboundedSender};
-use tokio_serial::{SerialPortBuilderExt};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tokio_serial::{ClearBuffer, SerialPort, SerialPortBuilderExt, SerialPortInfo, SerialPortType, SerialStream};
 use tokio_util::sync::CancellationToken;
-use crate::config::MachineConfig;
-use crate::tui::event::{AppEvent, Event};
-use crate::usb::Action::MOVE;
+use crate::config::{DisconnectPolicy, MachineConfig, UsbMatch};
+use crate::firmware::{FirmwareCapabilities, Position};
+use crate::backend::MotionBackend;
+use crate::command::{Action, Command, LinearAction, LinearModifier, QueuedCommand, Source};
+use crate::command::Action::MOVE;
+use crate::config::RestartPolicy;
+use crate::metrics::METRICS;
+use crate::queue::{CommandQueue, QueuePolicy};
+use crate::telemetry::{Diagnose, Latency, MotionMarker, SentGCode, Status, Telemetry, TelemetrySender};
 use crate::usb::GCodeError::UnsupportedMovement;
 
-#[derive(Debug, Clone)]
-pub enum Command {
-    Movement(LinearAction),
-    Home,
-    Halt,
-}
-
-#[derive(Debug, Clone)]
-pub struct LinearAction {
-    pub action: Action,
-    pub id: u32,
-    pub magnitude: u32,
-    pub modifier: Option<LinearModifier>
-}
-#[derive(Debug, Eq, PartialEq, Clone)]
-pub enum Action {
-    MOVE,
-    ROTATE,
-    VIBRATE,
-    AUXILLARY
-}
-
-#[derive(Debug, Clone)]
-pub enum LinearModifier {
-    TIME(u32),
-    SPEED(u32)
-}
 #[derive(Debug, Error)]
 pub enum GCodeError {
     #[error(transparent)]
@@ -43,90 +28,688 @@ pub enum GCodeError {
     Serial(#[from] tokio_serial::Error),
     #[error("Unsupported movement!")]
     UnsupportedMovement(Action),
+    #[error("No serial device matching USB id {0} is plugged in")]
+    NoMatchingDevice(UsbMatch),
+    #[error("Got garbage from the printer at {0} baud, is the baud rate right?")]
+    WrongBaudRate(u32),
+    #[error("Several serial devices match USB id {0} ({1:?}), add a serial number to pick one")]
+    AmbiguousDevice(UsbMatch, Vec<String>),
+}
+
+impl Diagnose for GCodeError {
+    fn diagnose(&self) -> (&'static str, &'static str, Option<&'static str>) {
+        use tokio_serial::ErrorKind as SerialKind;
+        const PERMISSION: Option<&str> = Some("Add your user to the dialout group (uucp on Arch), then log in again");
+        const NOT_FOUND: Option<&str> = Some("Plug the printer in, or pick the right Serial File");
+        match self {
+            GCodeError::Io(e) if e.kind() == io::ErrorKind::PermissionDenied => ("USB-PERM", "No permission!", PERMISSION),
+            GCodeError::Serial(e) if e.kind == SerialKind::Io(io::ErrorKind::PermissionDenied) => ("USB-PERM", "No permission!", PERMISSION),
+            GCodeError::Io(e) if e.kind() == io::ErrorKind::NotFound => ("USB-NOTFOUND", "Not connected!", NOT_FOUND),
+            GCodeError::Serial(e) if e.kind == SerialKind::NoDevice || e.kind == SerialKind::Io(io::ErrorKind::NotFound) => ("USB-NOTFOUND", "Not connected!", NOT_FOUND),
+            GCodeError::NoMatchingDevice(_) => ("USB-NODEV", "No device!", Some("Plug the printer in, or pick it again under Match USB id")),
+            GCodeError::WrongBaudRate(_) => ("USB-BAUD", "Wrong baud rate!", Some("Try 115200 or 250000, whichever the firmware was built with")),
+            GCodeError::AmbiguousDevice(..) => ("USB-MULTI", "Several devices!", Some("Pick the board with its serial number under Match USB id")),
+            _ => ("USB-ERR", "Error", None),
+        }
+    }
+}
+
+/// A Marlin (or Marlin-ish) printer on a serial port.
+#[derive(Debug, Clone)]
+pub struct MarlinSerial {
+    pub config: MachineConfig,
+}
+
+impl MotionBackend for MarlinSerial {
+    type Error = GCodeError;
+
+    fn name(&self) -> &'static str {
+        "USB"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.config.restart
+    }
+
+    fn queue_policy(&self) -> QueuePolicy {
+        self.config.queue_policy
+    }
+
+    async fn run(&mut self, rx: &mut CommandQueue, telemetry: TelemetrySender, token: CancellationToken) -> Result<(), GCodeError> {
+        let result = run_server(self.config.clone(), rx, telemetry, token).await;
+        if result.is_err() { METRICS.serial_errors.inc(); }
+        result
+    }
+}
+
+/// Work out which device node to open. With a USB id set this is whichever port currently has
+/// that id, so it doesn't matter if the printer came up as ttyUSB0, ttyUSB1 or ttyACM0 this time.
+pub fn resolve_port(config: &MachineConfig) -> Result<String, GCodeError> {
+    let Some(usb_id) = &config.usb_id else { return Ok(config.file.clone()) };
+    let port = matching_port(usb_id, tokio_serial::available_ports()?)?;
+    log::info!("USB id {} resolved to {}", usb_id, port);
+    Ok(port)
+}
+
+/// The one port in `ports` that `usb_id` matches.
+fn matching_port(usb_id: &UsbMatch, ports: Vec<SerialPortInfo>) -> Result<String, GCodeError> {
+    let mut matches: Vec<String> = ports
+        .into_iter()
+        .filter(|port| match &port.port_type {
+            SerialPortType::UsbPort(info) => info.vid == usb_id.vid && info.pid == usb_id.pid
+                && (usb_id.serial_number.is_none() || info.serial_number == usb_id.serial_number),
+            _ => false,
+        })
+        .map(|port| port.port_name)
+        .collect();
+    match matches.len() {
+        0 => Err(GCodeError::NoMatchingDevice(usb_id.clone())),
+        1 => Ok(matches.remove(0)),
+        _ => Err(GCodeError::AmbiguousDevice(usb_id.clone(), matches)),
+    }
+}
+/// How often to check the printer is still plugged in while idle, and to retry once it isn't.
+const PRESENCE_POLL: Duration = Duration::from_secs(1);
+/// How long DTR/RTS are held low for `reset_on_open`.
+const RESET_PULSE: Duration = Duration::from_millis(100);
+/// How long to listen for a startup banner. Boards take a couple of seconds to boot after a reset.
+const BANNER_WAIT: Duration = Duration::from_millis(500);
+const BANNER_WAIT_AFTER_RESET: Duration = Duration::from_secs(3);
+
+/// How long to wait for the firmware to answer a query like M115.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Feedrate used to park, mm/min. Deliberately slow.
+const PARK_FEEDRATE: u32 = 600;
+
+/// Lines written but not yet acknowledged before we stop taking commands off the queue. Anything
+/// past this would just sit in Marlin's buffers where a newer movement can't replace it.
+const MAX_IN_FLIGHT: usize = 2;
+/// Give up on an `ok` after this long (longer than a slow G28), so a lost one can't stall us forever.
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often to ask for the position with M114 when the firmware can't auto-report it.
+const POSITION_POLL: Duration = Duration::from_millis(250);
+
+/// Ids for sent lines, unique across reconnects so replies can't land on the wrong line.
+static NEXT_LINE_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An open port to a homed printer.
+struct Connection {
+    writer: WriteHalf<SerialStream>,
+    /// every line the printer sends, from a task reading the other half of the port.
+    replies: UnboundedReceiver<String>,
+    reader: JoinHandle<()>,
+    port: String,
+    /// `None` if the firmware didn't answer M115
+    firmware: Option<FirmwareCapabilities>,
+    /// paused or parked, movement is dropped until resumed
+    paused: bool,
+    /// lines the firmware hasn't said `ok` to yet, oldest first
+    in_flight: VecDeque<InFlight>,
+    /// lines received since the last `ok`, they belong to the oldest line in flight
+    reply: Vec<String>,
+    /// the G28 we're waiting on, we're ready once it's acknowledged
+    homing: Option<u64>,
+    /// X the carriage was last sent to, in mm, for working out how far the next move goes
+    x: f32,
+}
+
+/// A line written to the printer that hasn't been acknowledged yet.
+struct InFlight {
+    id: u64,
+    written: Instant,
+    /// when the movement it's for came in, `None` for anything else
+    received: Option<Instant>,
+    /// an M114 of ours, it doesn't take up a slot
+    poll: bool,
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Connection {
+    /// Write one line of G-code, remembering it so the firmware's `ok` can be matched up with it.
+    async fn send_line(&mut self, line: &str, source: Source, event_handler: &TelemetrySender) -> io::Result<u64> {
+        let bytes = format!("{}\n", line);
+        self.writer.write_all(bytes.as_bytes()).await?;
+        METRICS.gcode_lines.inc();
+        METRICS.bytes_written.add(bytes.len() as u64);
+        let id = NEXT_LINE_ID.fetch_add(1, Ordering::Relaxed);
+        self.in_flight.push_back(InFlight {
+            id,
+            written: Instant::now(),
+            received: None,
+            poll: false,
+            #[cfg(feature = "tracing")]
+            span: None,
+        });
+        let _ = event_handler.send(Telemetry::GCode(SentGCode {
+            id,
+            line: line.to_string(),
+            source,
+            sent_at: chrono::Local::now(),
+        }));
+        Ok(id)
+    }
+
+    /// Room for another line, otherwise commands wait in the queue. Position polls don't count,
+    /// they'd take a slot away from movement every time one goes out.
+    fn ready(&self) -> bool {
+        self.in_flight.iter().filter(|line| !line.poll).count() < MAX_IN_FLIGHT
+    }
+
+    /// M114, unless the last one hasn't been answered yet.
+    async fn poll_position(&mut self, event_handler: &TelemetrySender) -> io::Result<()> {
+        if self.in_flight.iter().any(|line| line.poll) { return Ok(()) }
+        self.send_line("M114", Source::Internal, event_handler).await?;
+        if let Some(line) = self.in_flight.back_mut() {
+            line.poll = true;
+        }
+        Ok(())
+    }
+
+    /// Forget lines whose `ok` never came, e.g. eaten by line noise.
+    fn expire_unacknowledged(&mut self) {
+        if self.in_flight.front().is_some_and(|line| line.written.elapsed() > ACK_TIMEOUT) {
+            log::warn!("No ok for {} line(s) after {:?}, carrying on without", self.in_flight.len(), ACK_TIMEOUT);
+            self.in_flight.clear();
+            self.reply.clear();
+        }
+    }
+
+    /// Note when the movement just written came in, so its latency can be reported once it's acknowledged.
+    fn track(&mut self, queued: &QueuedCommand) {
+        let Some(line) = self.in_flight.back_mut() else { return };
+        let queued_for = line.written.duration_since(queued.received);
+        METRICS.input_to_serial.observe(queued_for);
+        line.received = Some(queued.received);
+        #[cfg(feature = "tracing")]
+        {
+            queued.span.in_scope(|| tracing::debug!(line = line.id, queued_ms = queued_for.as_secs_f64() * 1e3, "written"));
+            line.span = Some(queued.span.clone());
+        }
+    }
+
+    /// G28, reporting homing until the firmware says it's done.
+    async fn home(&mut self, source: Source, event_handler: &TelemetrySender) -> io::Result<()> {
+        self.homing = Some(self.send_line("G28 X", source, event_handler).await?);
+        self.x = 0.0;
+        let _ = event_handler.send(Telemetry::BackendStatus(Status::Homing));
+        Ok(())
+    }
+
+    fn handle_reply(&mut self, line: &str, event_handler: &TelemetrySender) {
+        if let Some(position) = Position::parse(line) {
+            let _ = event_handler.send(Telemetry::ReportedPosition(position));
+        }
+        if line.starts_with("ok") {
+            self.reply.push(line.to_string());
+            match self.in_flight.pop_front() {
+                Some(line) => {
+                    let id = line.id;
+                    if let Some(received) = line.received {
+                        let latency = Latency { queued: line.written.duration_since(received), printer: line.written.elapsed() };
+                        METRICS.serial_to_ok.observe(latency.printer);
+                        #[cfg(feature = "tracing")]
+                        if let Some(span) = &line.span {
+                            span.in_scope(|| tracing::debug!(line = id, printer_ms = latency.printer.as_secs_f64() * 1e3, "acknowledged"));
+                        }
+                        let _ = event_handler.send(Telemetry::Latency(latency));
+                    }
+                    let _ = event_handler.send(Telemetry::GCodeReply(id, self.reply.join(" | ")));
+                    if self.homing == Some(id) {
+                        self.homing = None;
+                        let status = if self.paused { Status::Degraded("Paused".to_string()) } else { Status::Ready };
+                        let _ = event_handler.send(Telemetry::BackendStatus(status));
+                    }
+                }
+                None => log::trace!("ok with nothing in flight"),
+            }
+            self.reply.clear();
+        } else if !line.starts_with("echo:busy") { // busy is just a keepalive
+            log::trace!("Printer: {}", line);
+            self.reply.push(line.to_string());
+        }
+    }
+}
+
+pub async fn run_server(config: MachineConfig, rx: &mut CommandQueue, event_handler: TelemetrySender, token: CancellationToken) -> Result<(), GCodeError> {
+    // only the first time, while reconnecting the status says the printer is gone
+    let _ = event_handler.send(Telemetry::BackendStatus(Status::Binding));
+    let mut connection = connect(&config, &event_handler).await?;
+    let mut presence = tokio::time::interval(PRESENCE_POLL);
+    let mut position_poll = tokio::time::interval(POSITION_POLL);
+
+    loop {
+        let autoreport = connection.firmware.as_ref().is_some_and(FirmwareCapabilities::autoreport_pos);
+        let ready = connection.ready();
+        let result = tokio::select! {
+            _ = token.cancelled() => break,
+            _ = presence.tick() => if port_present(&connection.port) {
+                connection.expire_unacknowledged();
+                continue
+            } else {
+                Err(io::Error::new(io::ErrorKind::NotConnected, format!("{} went away", connection.port)).into())
+            },
+            _ = position_poll.tick(), if !autoreport => connection.poll_position(&event_handler).await.map_err(GCodeError::from),
+            line = connection.replies.recv() => match line {
+                Some(line) => {
+                    connection.handle_reply(&line, &event_handler);
+                    Ok(())
+                }
+                None => Err(io::Error::new(io::ErrorKind::BrokenPipe, format!("{} stopped responding", connection.port)).into()),
+            },
+            // only take the next command once the printer has caught up, so stale movement piles
+            // up in the queue where it can be coalesced. a halt can't wait for that.
+            command = async { if ready { rx.recv().await } else { rx.recv_halt().await } } => match command {
+                Some(command) => send_command(&mut connection, &config, &command, &event_handler).await,
+                None => break,
+            },
+        };
+        match result {
+            // the cable got bumped (or the board reset), wait for it to come back rather than taking
+            // the websocket side down with us.
+            Err(GCodeError::Io(e)) => {
+                log::warn!("Lost connection to printer: {}", e);
+                METRICS.serial_errors.inc();
+                match reconnect(&config, rx, &event_handler, &token).await {
+                    Some(reconnected) => connection = reconnected,
+                    None => break,
+                }
+            }
+            r => r?,
+        }
+    }
+    Ok(())
+}
+
+/// Open the port and home, ready for movement.
+async fn connect(config: &MachineConfig, event_handler: &TelemetrySender) -> Result<Connection, GCodeError> {
+    let port = resolve_port(config)?;
+    let mut serial = tokio_serial::new(port.clone(), config.baud_rate)
+        .flow_control(config.flow_control.into())
+        .open_native_async()?;
+
+    if config.reset_on_open {
+        serial.write_data_terminal_ready(false)?;
+        serial.write_request_to_send(false)?;
+        tokio::time::sleep(RESET_PULSE).await;
+        serial.write_data_terminal_ready(true)?;
+        serial.write_request_to_send(true)?;
+    }
+    probe_banner(&mut serial, config).await?;
+    let _ = event_handler.send(Telemetry::BackendStatus(Status::Connected(port.clone())));
+
+    let firmware = FirmwareCapabilities::parse(query(&mut serial, "M115").await?.iter().map(String::as_str));
+    match &firmware {
+        Some(firmware) => log::info!("Connected to {} ({}), {} capabilities", firmware.firmware_name, firmware.kind, firmware.caps.len()),
+        None => log::warn!("Firmware didn't answer M115, assuming nothing about it"),
+    }
+    let _ = event_handler.send(Telemetry::Firmware(firmware.clone()));
+
+    let autoreport = firmware.as_ref().is_some_and(FirmwareCapabilities::autoreport_pos);
+
+    let (reader, writer) = tokio::io::split(serial);
+    let (replies_tx, replies) = tokio::sync::mpsc::unbounded_channel();
+    let reader = tokio::spawn(read_lines(reader, replies_tx));
+    let mut connection = Connection {
+        writer, replies, reader, port, firmware,
+        paused: false,
+        in_flight: VecDeque::new(),
+        reply: Vec::new(),
+        homing: None,
+        x: 0.0,
+    };
+
+    if autoreport {
+        connection.send_line("M154 S1", Source::Internal, event_handler).await?; // report position every second without being asked
+    }
+    connection.home(Source::Internal, event_handler).await?;
+    Ok(connection)
+}
+
+/// Forward each line the printer sends until the port closes or errors.
+async fn read_lines(reader: ReadHalf<SerialStream>, tx: UnboundedSender<String>) {
+    let mut lines = BufReader::new(reader).lines();
+    loop {
+        match lines.next_line().await {
+            Ok(Some(line)) => if tx.send(line.trim().to_string()).is_err() { break },
+            Ok(None) => break,
+            Err(e) => {
+                log::debug!("Serial read failed: {}", e);
+                break;
+            }
+        }
+    }
 }
-pub async fn run_server(config: MachineConfig, mut rx: Receiver<Command>, event_handler: UnboundedSender<Event>, token: CancellationToken) -> Result<(), GCodeError> {
-    let mut serial = tokio_serial::new(config.file.clone(), 250000).open_native_async()?;
-
-    serial.write_all(b"G28 X\n").await?;
-    serial.flush().await?; // ensure
-    let _ = event_handler.send(Event::App(AppEvent::GCode("G28 X".to_string())));
-
-    while !token.is_cancelled() && let Some(command) = &rx.recv().await {
-        log::debug!("{:?}", command);
-        let mut last_linear_action: Option<LinearAction> = None;
-        match command {
-            Command::Movement(action) => {
-                let gcode = &*create_gcode(&config, &action, last_linear_action)?;
-                let ret =serial.write(gcode).await?;
-                last_linear_action = Some(action.clone());
-                let _ =event_handler.send(Event::App(AppEvent::GCode(format!("{:?}", gcode))));
+
+/// Send `gcode` and collect the reply lines up to (not including) the `ok`. A reply that's mostly
+/// garbage is [`GCodeError::WrongBaudRate`], which catches boards that stayed quiet on open.
+async fn query(serial: &mut SerialStream, gcode: &str) -> Result<Vec<String>, GCodeError> {
+    serial.clear(ClearBuffer::Input)?; // don't want leftover "ok"s from before
+    let bytes = format!("{}\n", gcode);
+    serial.write_all(bytes.as_bytes()).await?;
+    serial.flush().await?;
+    METRICS.gcode_lines.inc();
+    METRICS.bytes_written.add(bytes.len() as u64);
+
+    let mut lines = Vec::new();
+    let mut received = Vec::new();
+    let mut pending = Vec::new();
+    let mut buf = [0u8; 256];
+    let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+    let mut acknowledged = false;
+    'read: while let Ok(read) = tokio::time::timeout_at(deadline, serial.read(&mut buf)).await {
+        let read = &buf[..read?];
+        received.extend_from_slice(read);
+        pending.extend_from_slice(read);
+        while let Some(end) = pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line).trim().to_string();
+            if line.starts_with("ok") {
+                acknowledged = true;
+                break 'read;
             }
-            Command::Halt => {
-                serial.write_all(b"M112\n").await?;
-                serial.flush().await?;
-                let _ = event_handler.send(Event::App(AppEvent::GCode("M112".to_string())));
+            lines.push(line);
+        }
+    }
+    if garbled(&received) {
+        return Err(GCodeError::WrongBaudRate(serial.baud_rate()?));
+    }
+    if !acknowledged {
+        log::warn!("No ok for {} after {:?}", gcode, QUERY_TIMEOUT);
+    }
+    Ok(lines)
+}
+
+/// Keep trying to connect until the printer is back. Incoming movement is handled according to
+/// [`DisconnectPolicy`] in the meantime, everything else (home, halt...) is held and sent in order
+/// once it's back, a halt replacing whatever was held before it. `None` if we were cancelled or
+/// the input side went away.
+async fn reconnect(config: &MachineConfig, rx: &mut CommandQueue, event_handler: &TelemetrySender, token: &CancellationToken) -> Option<Connection> {
+    let _ = event_handler.send(Telemetry::BackendStatus(Status::Degraded("Printer disconnected".to_string())));
+    let mut held: VecDeque<QueuedCommand> = VecDeque::new();
+    let mut retry = tokio::time::interval(PRESENCE_POLL);
+    loop {
+        tokio::select! {
+            _ = token.cancelled() => return None,
+            command = rx.recv() => match command {
+                Some(queued) => hold(config, &mut held, queued, event_handler),
+                None => return None,
             },
-            Command::Home => {
-                serial.write_all(b"G28 X\n").await?;
-                serial.flush().await?; // ensure
-                let _ = event_handler.send(Event::App(AppEvent::GCode("G28 X".to_string())));
+            _ = retry.tick() => match connect(config, event_handler).await {
+                Ok(mut connection) => {
+                    log::info!("Printer reconnected on {}", connection.port);
+                    while let Some(command) = held.pop_front() {
+                        if let Err(e) = send_command(&mut connection, config, &command, event_handler).await {
+                            log::warn!("Could not send held {:?} after reconnecting: {}", command.command, e);
+                            held.push_front(command);
+                            break;
+                        }
+                    }
+                    if !held.is_empty() { continue; }
+                    return Some(connection);
+                }
+                Err(e) => log::trace!("Printer still not back: {}", e),
             },
         }
     }
+}
+
+/// What to do with a command that came in while disconnected, see [`reconnect`].
+fn hold(config: &MachineConfig, held: &mut VecDeque<QueuedCommand>, queued: QueuedCommand, event_handler: &TelemetrySender) {
+    match &queued.command {
+        Command::Movement(_) if config.disconnect_policy == DisconnectPolicy::HoldLatest => {
+            // only the newest movement is worth going to
+            held.retain(|q| !matches!(q.command, Command::Movement(_)));
+            held.push_back(queued);
+        }
+        Command::Movement(action) => {
+            log::debug!("Printer disconnected, dropping {:?}", queued.command);
+            METRICS.dropped_disconnected.inc();
+            let _ = event_handler.send(Telemetry::Marker(MotionMarker::Dropped, target_x(config, action)));
+        }
+        Command::Halt => {
+            log::info!("Printer disconnected, it'll be halted once it's back");
+            for cancelled in held.drain(..) {
+                if let Command::Movement(_) = cancelled.command { METRICS.dropped_halted.inc(); }
+            }
+            held.push_back(queued);
+        }
+        command => {
+            log::info!("Printer disconnected, holding {:?} until it's back", command);
+            held.push_back(queued);
+        }
+    }
+}
+
+/// Listen for whatever the firmware prints on startup ("start", "Marlin 2.1.2", "echo:..."). Text
+/// means the baud rate is right, garbage means it isn't. Silence is fine, not every board resets
+/// when the port is opened, the M115 [`query`] after this checks its reply the same way.
+async fn probe_banner(serial: &mut SerialStream, config: &MachineConfig) -> Result<(), GCodeError> {
+    let wait = if config.reset_on_open { BANNER_WAIT_AFTER_RESET } else { BANNER_WAIT };
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    let deadline = tokio::time::Instant::now() + wait;
+    while let Ok(read) = tokio::time::timeout_at(deadline, serial.read(&mut buf)).await {
+        received.extend_from_slice(&buf[..read?]);
+        if received.len() >= 512 { break; }
+    }
+
+    if received.is_empty() {
+        log::warn!("No startup banner from the printer, carrying on anyway");
+        return Ok(());
+    }
+    if garbled(&received) {
+        return Err(GCodeError::WrongBaudRate(config.baud_rate));
+    }
+    log::info!("Printer says: {}", String::from_utf8_lossy(&received).trim());
+    Ok(())
+}
+
+/// At the wrong baud rate text comes out as mostly unprintable bytes. Less than 80% printable counts.
+fn garbled(received: &[u8]) -> bool {
+    let printable = received.iter()
+        .filter(|&&b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+        .count();
+    received.len() >= 8 && printable * 10 < received.len() * 8
+}
+
+/// Whether the device node is still there. Checked on the path itself rather than against the
+/// port list, so `/dev/serial/by-id/...` symlinks and ptys count too.
+fn port_present(port: &str) -> bool {
+    Path::new(port).exists()
+}
+
+async fn send_command(connection: &mut Connection, config: &MachineConfig, queued: &QueuedCommand, event_handler: &TelemetrySender) -> Result<(), GCodeError> {
+    let QueuedCommand { source, command, .. } = queued;
+    let source = *source;
+    log::debug!("{:?} from {}", command, source);
+    match command {
+        Command::Movement(action) if connection.paused => {
+            log::trace!("Paused, dropping {:?}", command);
+            METRICS.dropped_paused.inc();
+            let _ = event_handler.send(Telemetry::Marker(MotionMarker::Dropped, target_x(config, action)));
+        }
+        Command::Movement(action) => {
+            let GCodeMove { gcode, limited } = create_gcode(config, action, connection.x)?;
+            connection.send_line(&gcode, source, event_handler).await?;
+            connection.track(queued);
+            let x = target_x(config, action);
+            connection.x = x;
+            let _ = event_handler.send(Telemetry::CommandedPosition(x));
+            if limited {
+                METRICS.limited.inc();
+                let _ = event_handler.send(Telemetry::Marker(MotionMarker::Limited, x));
+            }
+        }
+        Command::Halt => {
+            if !connection.firmware.as_ref().is_some_and(FirmwareCapabilities::emergency_parser) {
+                // without the emergency parser M112 sits behind every line already sent, so stop
+                // sending anything else. the planner drains what it has, then M112 runs.
+                log::warn!("Firmware has no EMERGENCY_PARSER, halting once {} queued line(s) finish", connection.in_flight.len());
+                connection.paused = true;
+                let _ = event_handler.send(Telemetry::BackendStatus(Status::Degraded("Halting after queued moves (no EMERGENCY_PARSER)".to_string())));
+            }
+            connection.send_line("M112", source, event_handler).await?;
+        },
+        Command::Home => {
+            connection.home(source, event_handler).await?;
+        },
+        Command::Pause => {
+            // M410 throws away everything in the planner and stops, the printer stays usable.
+            connection.send_line("M410", source, event_handler).await?;
+            connection.paused = true;
+            let _ = event_handler.send(Telemetry::BackendStatus(Status::Degraded("Paused".to_string())));
+        },
+        Command::Park => {
+            connection.send_line("M410", source, event_handler).await?;
+            let park = format!("G1 X{:.2} F{}", config.stroke_base(), PARK_FEEDRATE);
+            connection.send_line(&park, source, event_handler).await?;
+            connection.x = config.stroke_base();
+            connection.paused = true;
+            let _ = event_handler.send(Telemetry::BackendStatus(Status::Degraded("Parked".to_string())));
+        },
+        Command::Resume => {
+            connection.paused = false;
+            let status = if connection.homing.is_some() { Status::Homing } else { Status::Ready };
+            let _ = event_handler.send(Telemetry::BackendStatus(status));
+        },
+        Command::Raw(line) => {
+            connection.send_line(line, source, event_handler).await?;
+        },
+    }
     Ok(())
 }
 
-fn create_gcode(config: &MachineConfig, action: &LinearAction, last_action: Option<LinearAction>) -> Result<Vec<u8>, GCodeError> {
-    let last_action = last_action.unwrap_or_else(|| LinearAction {
-        action: Action::MOVE,
-        id: 0,
-        magnitude: 0,
-        modifier: None,
-    });
+/// A G1 for one movement.
+struct GCodeMove {
+    gcode: String,
+    /// the feedrate was clipped to `max_feedrate`, so the move will take longer than asked.
+    limited: bool,
+}
 
+/// `from_x` is where the carriage is coming from, for turning a move's duration into a feedrate.
+fn create_gcode(config: &MachineConfig, action: &LinearAction, from_x: f32) -> Result<GCodeMove, GCodeError> {
     if action.action != MOVE { return Err(UnsupportedMovement(action.action.clone())); }
     // now lets make a gcode for it
-    let mut output = String::from("G1 X");
-    let distance = action.magnitude_to_distance(config.max_movement);
-
-    output.push_str(&format!("{:.2} ", (config.throw - config.max_movement) as f32+ distance));
+    let x = target_x(config, action);
+    let mut output = format!("G1 X{:.2} ", x);
+    let mut limited = false;
+    let mut limit = |speed: f32| if speed > config.max_feedrate as f32 {
+        limited = true;
+        config.max_feedrate as f32
+    } else { speed };
     // distance is in MM so speed is MM/h.ms -> MM/min
     let feedrate = match action.modifier {
-        Some(LinearModifier::SPEED(mmPerHundredMs)) => {
-            if let Some(LinearModifier::SPEED(last)) = last_action.modifier && last == mmPerHundredMs{
-                String::new() // same as previous we dont need to redo
-            } else { format!("F{}\n", mmPerHundredMs*600) }
-        },
-        //Some(LinearModifier::SPEED(_)) => { String::from("F2000") } // limited speed ver.
+        Some(LinearModifier::SPEED(mm_per_hundred_ms)) => format!("F{}", limit((mm_per_hundred_ms * 600) as f32)),
         Some(LinearModifier::TIME(ms)) => {
-            if let Some(LinearModifier::TIME(last)) = last_action.modifier && last == ms {
-                String::new() // same as previous we dont need to redo.
+            let distance = (x - from_x).abs();
+            if distance > 0.0 {
+                let speed = limit(distance / (ms.max(1) as f32 / 60_000.00)); // if the speed goes haywire we start forcing it to slow.
+                format!("F{:.2}", speed)
             } else {
-                let previous_distance = last_action.magnitude_to_distance(config.max_movement);
-                let d= ((distance-previous_distance).abs());
-                if (d > 1f32) {
-                    let speed = d / (ms as f32 / 60_000.00);
-                    //if speed > 7000f32 { speed = 2000f32; } // if the speed goes haywire we start forcing it to slow.
-                    format!("F{:.2}\n", speed)
-                } else {
-                    String::new() // use previous speed if really close.
-                }
+                String::new() // not going anywhere, an F0 would just stall the next move
             }
         }
         None => "".to_string()
     };
     output.push_str(&feedrate);
-    log::info!("{}", output);
-    Ok(output.as_bytes().to_vec())
+    let gcode = output.trim_end().to_string();
+    log::info!("{}", gcode);
+    Ok(GCodeMove { gcode, limited })
+}
+
+/// Where on the X axis `action` will put the carriage, in mm.
+pub fn target_x(config: &MachineConfig, action: &LinearAction) -> f32 {
+    config.stroke_base() + action.magnitude_to_distance(config.max_movement)
 }
 
 impl LinearAction {
     // max distance (mm) -> distance on scale in mm
     pub fn magnitude_to_distance(&self, max_distance: u32) -> f32 {
-        if self.magnitude <= 0 { return 0f32; }
-        let digits = self.magnitude.ilog10() + 1;
-        let distance = max_distance as f32 * (self.magnitude as f32 / 10f32.powi(digits as i32));
+        max_distance as f32 * self.position.clamp(0.0, 1.0)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn status_stays_disconnected_while_retrying() {
+        let config = MachineConfig { file: "/nonexistent/ttyUSB0".to_string(), ..MachineConfig::default() };
+        let (_tx, rx) = mpsc::channel(10);
+        let mut queue = CommandQueue::new(rx, QueuePolicy::LatestWins);
+        let (events, mut telemetry) = mpsc::unbounded_channel();
+        let token = CancellationToken::new();
+        // a few retries' worth
+        let retrying = tokio::time::timeout(PRESENCE_POLL * 3 + PRESENCE_POLL / 2, reconnect(&config, &mut queue, &events, &token)).await;
+        assert!(retrying.is_err(), "nothing to reconnect to");
+
+        let mut statuses = Vec::new();
+        while let Ok(t) = telemetry.try_recv() {
+            if let Telemetry::BackendStatus(status) = t { statuses.push(status); }
+        }
+        assert_eq!(statuses.len(), 1);
+        assert!(matches!(&statuses[0], Status::Degraded(reason) if reason == "Printer disconnected"));
+    }
+
+    fn held_commands(policy: DisconnectPolicy, commands: Vec<Command>) -> Vec<String> {
+        let config = MachineConfig { disconnect_policy: policy, ..MachineConfig::default() };
+        let (events, _telemetry) = mpsc::unbounded_channel();
+        let mut held = VecDeque::new();
+        for command in commands {
+            hold(&config, &mut held, QueuedCommand::new(Source::Internal, command), &events);
+        }
+        held.iter().map(|q| match &q.command {
+            Command::Movement(action) => action.position.to_string(),
+            other => format!("{other:?}"),
+        }).collect()
+    }
 
-        distance.min(max_distance as f32)
+    fn movement(position: f32) -> Command {
+        Command::Movement(LinearAction { action: MOVE, id: 0, position, modifier: None })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn home_and_halt_are_held_while_disconnected() {
+        assert_eq!(held_commands(DisconnectPolicy::Drop, vec![movement(0.1), Command::Home, movement(0.2)]), ["Home"]);
+        assert_eq!(held_commands(DisconnectPolicy::HoldLatest, vec![movement(0.1), Command::Home, movement(0.2), movement(0.3)]), ["Home", "0.3"]);
+        // a halt replaces what was held before it
+        assert_eq!(held_commands(DisconnectPolicy::HoldLatest, vec![Command::Home, movement(0.2), Command::Halt, movement(0.3)]), ["Halt", "0.3"]);
+    }
+
+    fn usb_port(name: &str, vid: u16, pid: u16, serial_number: Option<&str>) -> SerialPortInfo {
+        let info = tokio_serial::UsbPortInfo { vid, pid, serial_number: serial_number.map(str::to_string), manufacturer: None, product: None };
+        SerialPortInfo { port_name: name.to_string(), port_type: SerialPortType::UsbPort(info) }
+    }
+
+    fn ports() -> Vec<SerialPortInfo> {
+        vec![
+            SerialPortInfo { port_name: "/dev/ttyS0".to_string(), port_type: SerialPortType::Unknown },
+            usb_port("/dev/ttyUSB0", 0x1a86, 0x7523, None),
+            usb_port("/dev/ttyACM0", 0x2341, 0x0042, Some("A")),
+            usb_port("/dev/ttyACM1", 0x2341, 0x0042, Some("B")),
+        ]
+    }
+
+    #[test]
+    fn port_matching() {
+        let usb_id = |s: &str| s.parse::<UsbMatch>().unwrap();
+        assert_eq!(matching_port(&usb_id("1a86:7523"), ports()).unwrap(), "/dev/ttyUSB0");
+        assert_eq!(matching_port(&usb_id("2341:0042:B"), ports()).unwrap(), "/dev/ttyACM1");
+        assert!(matches!(matching_port(&usb_id("2341:0043"), ports()), Err(GCodeError::NoMatchingDevice(_))));
+        assert!(matches!(matching_port(&usb_id("2341:0042:C"), ports()), Err(GCodeError::NoMatchingDevice(_))));
+        match matching_port(&usb_id("2341:0042"), ports()) {
+            Err(GCodeError::AmbiguousDevice(_, found)) => assert_eq!(found, ["/dev/ttyACM0", "/dev/ttyACM1"]),
+            other => panic!("expected both boards, got {other:?}"),
+        }
+    }
+}
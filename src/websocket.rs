@@ -1,16 +1,21 @@
 use crate::config::{WebsocketConfig};
 use crate::extoy_de::ExtoyPacket;
 use crate::tcode_de::LinearActionError;
-use crate::usb::LinearModifier::TIME;
-use crate::usb::{Action, LinearAction};
+use crate::command::LinearModifier::TIME;
+use crate::command::{Action, LinearAction, QueuedCommand, Source};
 use crate::websocket::ClientError::{InvalidListener};
-use crate::{tcode_de, Command};
+use crate::tcode_de;
+use crate::command::Command;
 use futures_util::SinkExt;
 use futures_util::{StreamExt, TryStreamExt};
 use thiserror::Error;
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc::error::SendError;
 use tokio::sync::mpsc::Sender;
+use crate::config::RestartPolicy;
+use crate::input::InputSource;
+use crate::metrics::METRICS;
+use crate::telemetry::{Diagnose, Status, Telemetry, TelemetrySender};
 use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 use tokio_tungstenite::tungstenite::protocol::{CloseFrame};
 use tokio_tungstenite::tungstenite::{Message, Utf8Bytes};
@@ -24,7 +29,7 @@ pub enum ClientError {
     #[error(transparent)]
     LinearAction(#[from] LinearActionError),
     #[error(transparent)]
-    Mspc(#[from] SendError<Command>),
+    Mspc(#[from] SendError<QueuedCommand>),
     #[error("not valid uri")]
     InvalidListener,
     #[error(transparent)]
@@ -35,10 +40,78 @@ pub enum ClientError {
     UnsupportedAction,
 }
 
-pub(crate) async fn extoys(config: &WebsocketConfig, tx: Sender<Command>, token: CancellationToken) -> Result<(), ClientError> {
+impl Diagnose for ClientError {
+    fn diagnose(&self) -> (&'static str, &'static str, Option<&'static str>) {
+        match self {
+            ClientError::Io(e) if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+                ("WS-URI", "Invalid URI", Some("Check the Websocket URI, e.g. ws://localhost:12345")),
+            ClientError::InvalidListener =>
+                ("WS-URI", "Invalid URI", Some("Check the Websocket URI, e.g. ws://localhost:12345")),
+            ClientError::Io(e) if e.kind() == std::io::ErrorKind::ConnectionRefused =>
+                ("WS-REFUSED", "Refused", Some("Is Intiface Central running with its server started, on the port in the URI?")),
+            ClientError::Io(e) if e.kind() == std::io::ErrorKind::AddrInUse =>
+                ("WS-INUSE", "Address in use", Some("Something else is listening on that port, close it or pick another")),
+            ClientError::Tungstenite(_) =>
+                ("WS-PROTO", "Error", Some("Check the Service Provider matches what's on the other end")),
+            _ => ("WS-ERR", "Error", None),
+        }
+    }
+}
+
+/// Intiface Central connecting us as a websocket device.
+#[derive(Debug, Clone)]
+pub struct Intiface {
+    pub config: WebsocketConfig,
+}
+
+impl InputSource for Intiface {
+    type Error = ClientError;
+
+    fn name(&self) -> &'static str {
+        "Intiface"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.config.restart
+    }
+
+    async fn run(&mut self, tx: Sender<QueuedCommand>, telemetry: TelemetrySender, token: CancellationToken) -> Result<(), ClientError> {
+        intiface(&self.config, tx, telemetry, token).await
+    }
+}
+
+/// XToys' websocket output, we're the server here.
+#[derive(Debug, Clone)]
+pub struct XToys {
+    pub config: WebsocketConfig,
+}
+
+impl InputSource for XToys {
+    type Error = ClientError;
+
+    fn name(&self) -> &'static str {
+        "XToys"
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.config.restart
+    }
+
+    async fn run(&mut self, tx: Sender<QueuedCommand>, telemetry: TelemetrySender, token: CancellationToken) -> Result<(), ClientError> {
+        extoys(&self.config, tx, telemetry, token).await
+    }
+}
+
+async fn extoys(config: &WebsocketConfig, tx: Sender<QueuedCommand>, events: TelemetrySender, token: CancellationToken) -> Result<(), ClientError> {
+    let _ = events.send(Telemetry::InputStatus(Status::Binding));
     let listener = TcpListener::bind(config.ws.strip_prefix("ws://").ok_or(InvalidListener)?).await?;
-    let (stream,_) = listener.accept().await?;
+    let _ = events.send(Telemetry::InputStatus(Status::Listening(listener.local_addr()?.to_string())));
+    let (stream, peer) = listener.accept().await?;
+    log::info!("XToys connected from {}", peer);
+    let _ = events.send(Telemetry::InputStatus(Status::Connected(peer.to_string())));
     let mut websocket = accept_async(stream).await?;
+    METRICS.websocket_connects.inc();
+    let _ = events.send(Telemetry::InputStatus(Status::Handshaken));
 
     // position logic:
     // move to position at some speed
@@ -46,21 +119,16 @@ pub(crate) async fn extoys(config: &WebsocketConfig, tx: Sender<Command>, token:
         if let Message::Text(b) = &msg {
             let str = b.as_str();
             let packet: ExtoyPacket = serde_json::from_str(str)?;
-            if let ExtoyPacket::Position { position, mut duration} = packet {
-                let BASE_F = 10;
-                let ACTUAL_F = 50;
-                if BASE_F != ACTUAL_F {
-                    duration = (duration as f32 * (BASE_F as f32 / ACTUAL_F as f32)) as u32;
-                }
+            if let ExtoyPacket::Position { position, duration} = packet {
                 log::debug!("Received extoy packet: {:?}", packet);
                 let action = LinearAction {
                     action: Action::MOVE,
                     id: 0,
-                    magnitude: position.min(99) as u32,
+                    position: (position as f32 / 100.0).min(1.0),
                     modifier: Some(TIME(duration as u32))
                 };
                 log::debug!("Processed action: {:?}", action);
-                tx.send(Command::Movement(action)).await?;
+                tx.send(QueuedCommand::new(Source::XToys, Command::Movement(action))).await?;
             } else {
                 log::error!("unsupported speed action will be ignored !");
             }
@@ -87,12 +155,20 @@ pub(crate) async fn extoys(config: &WebsocketConfig, tx: Sender<Command>, token:
     Ok(())
 }
 
-pub async fn intiface(config:&WebsocketConfig, tx: Sender<Command>, token: CancellationToken) -> Result<(), ClientError> {
+async fn intiface(config:&WebsocketConfig, tx: Sender<QueuedCommand>, events: TelemetrySender, token: CancellationToken) -> Result<(), ClientError> {
+    let _ = events.send(Telemetry::InputStatus(Status::Connecting(config.ws.clone())));
     let (mut websocket, _) = connect_async(config.ws.as_str()).await?;
+    METRICS.websocket_connects.inc();
+    let peer = match websocket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.peer_addr().map_or(config.ws.clone(), |a| a.to_string()),
+        _ => config.ws.clone(),
+    };
+    let _ = events.send(Telemetry::InputStatus(Status::Connected(peer)));
 
     websocket.send(Message::Text(Utf8Bytes::from(
         format!("{{\"identifier\":\"{0}\",\"address\":\"{1}\",\"version\":0}}", "UpYourEnder", 2))
     )).await?;
+    let _ = events.send(Telemetry::InputStatus(Status::Handshaken));
 
     while !token.is_cancelled() && let Some(msg) = &websocket.try_next().await? {
         let msg = msg;
@@ -100,7 +176,7 @@ pub async fn intiface(config:&WebsocketConfig, tx: Sender<Command>, token: Cance
         if let Message::Binary(bytes) = msg {
             let linear_action = tcode_de::process_linear_token(&bytes[..(bytes.len()-1)]);
             if let Ok(action) = linear_action {
-                tx.send(Command::Movement(action)).await?;
+                tx.send(QueuedCommand::new(Source::Intiface, Command::Movement(action))).await?;
             } else if let Err(e) = linear_action { // if strict is not enabled silently ignore.
                 return Err(ClientError::LinearAction(e));
             }